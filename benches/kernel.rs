@@ -0,0 +1,28 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use ndarray::Array1;
+use ndarray_rand::{rand_distr::Uniform, RandomExt};
+use vboo::ranking::{
+    kernel::{sum, sum_portable},
+    op::Op,
+};
+
+pub fn criterion_benchmark(c: &mut Criterion) {
+    let q = Array1::random(256, Uniform::<f32>::new(0., 1.)).to_vec();
+    let d = Array1::random(256, Uniform::<f32>::new(0., 1.)).to_vec();
+
+    for op in [Op::OR, Op::AND] {
+        let mut group = c.benchmark_group(format!("{:?}", op).to_lowercase());
+        for p in [1.0, 2.0, 3.0, 2.5] {
+            group.bench_with_input(BenchmarkId::new("portable", p), &p, |b, p| {
+                b.iter(|| sum_portable(black_box(&q), black_box(&d), &op, *p))
+            });
+            group.bench_with_input(BenchmarkId::new("dispatched", p), &p, |b, p| {
+                b.iter(|| sum(black_box(&q), black_box(&d), &op, *p))
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,402 @@
+use super::{request, Page};
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// What's known of a cached page without reading its body.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// the url the page was fetched by, its key in the cache
+    pub url: String,
+    /// when the page was last downloaded or revalidated
+    pub fetched: SystemTime,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// bytes of the body
+    pub size: u64,
+}
+
+/// Downloaded pages kept on disk, keyed by url.
+///
+/// Each page is two files named by a hash of its url, the body as is and a
+/// `.meta` of `key=value` lines holding the rest of the page and the entry.
+/// Both are written to a temporary file and renamed into place, so neither
+/// is ever left half written.
+/// A cached page is revalidated before it's used, with `If-None-Match` and
+/// `If-Modified-Since` when the site gave an etag or last modified date,
+/// so unchanged pages aren't downloaded again.
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    /// open the cache in a directory, creating it if need be
+    pub fn open(dir: &Path) -> io::Result<Cache> {
+        fs::create_dir_all(dir)?;
+        Ok(Cache {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    /// `$XDG_CACHE_HOME/vboo`, or `~/.cache/vboo`
+    pub fn default_dir() -> PathBuf {
+        match (env::var_os("XDG_CACHE_HOME"), env::var_os("HOME")) {
+            (Some(cache), _) if !cache.is_empty() => PathBuf::from(cache).join("vboo"),
+            (_, Some(home)) => PathBuf::from(home).join(".cache").join("vboo"),
+            _ => env::temp_dir().join("vboo"),
+        }
+    }
+
+    /// the page at the url, from the cache when it's unchanged
+    ///
+    /// Offline, only the cache is used and pages not in it are errors. Online,
+    /// a cached page is revalidated and downloaded again when it changed or
+    /// can't be revalidated. When the site can't be reached at all the cached
+    /// page is used as is.
+    pub fn fetch(&self, url: &str, offline: bool) -> io::Result<Page> {
+        let cached = self.get(url)?;
+        let (entry, page) = match cached {
+            Some(cached) if offline => return Ok(cached.1),
+            None if offline => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{} isn't cached", url),
+                ))
+            }
+            None => {
+                let page = super::fetch(url)?;
+                self.put(url, &page)?;
+                return Ok(page);
+            }
+            Some(cached) => cached,
+        };
+
+        let mut conditions = Vec::new();
+        if let Some(etag) = &entry.etag {
+            conditions.push(format!("If-None-Match: {}", etag));
+        }
+        if let Some(last_modified) = &entry.last_modified {
+            conditions.push(format!("If-Modified-Since: {}", last_modified));
+        }
+        match request(url, &conditions) {
+            Ok((304, fresh)) => {
+                let page = revalidated(page, &fresh);
+                self.write_meta(url, &page, SystemTime::now())?;
+                Ok(page)
+            }
+            Ok((status, _)) if status >= 400 => {
+                Err(io::Error::other(format!("{} responded {}", url, status)))
+            }
+            Ok((_, page)) => {
+                self.put(url, &page)?;
+                Ok(page)
+            }
+            Err(_) => Ok(page),
+        }
+    }
+
+    /// the cached page at the url
+    ///
+    /// A page cached under another url whose hash collides with this one's
+    /// isn't it, and counts as not cached.
+    pub fn get(&self, url: &str) -> io::Result<Option<(Entry, Page)>> {
+        let meta = match fs::read_to_string(self.path(url, "meta")) {
+            Ok(meta) => meta,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let (entry, mut page) = parse(&meta)?;
+        if entry.url != url {
+            return Ok(None);
+        }
+        page.body = fs::read_to_string(self.path(url, "body"))?;
+        Ok(Some((entry, page)))
+    }
+
+    /// cache the page as fetched now by the url
+    pub fn put(&self, url: &str, page: &Page) -> io::Result<Entry> {
+        self.replace(url, "body", &page.body)?;
+        self.write_meta(url, page, SystemTime::now())
+    }
+
+    /// every cached page, by url
+    pub fn entries(&self) -> io::Result<Vec<Entry>> {
+        let mut entries = Vec::new();
+        for file in fs::read_dir(&self.dir)? {
+            let path = file?.path();
+            if path.extension().is_some_and(|e| e == "meta") {
+                entries.push(parse(&fs::read_to_string(path)?)?.0);
+            }
+        }
+        entries.sort_by(|a, b| a.url.cmp(&b.url));
+        Ok(entries)
+    }
+
+    /// remove the pages at these urls from the cache, or every page when
+    /// there are none, returning how many were removed
+    pub fn purge(&self, urls: &[&str]) -> io::Result<usize> {
+        let entries = self.entries()?;
+        let mut removed = 0;
+        for entry in entries {
+            if urls.is_empty() || urls.contains(&entry.url.as_str()) {
+                fs::remove_file(self.path(&entry.url, "meta"))?;
+                // the body is unreadable without its meta, so gone either way
+                let _ = fs::remove_file(self.path(&entry.url, "body"));
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    fn path(&self, url: &str, extension: &str) -> PathBuf {
+        self.dir.join(format!("{:016x}.{}", fnv1a(url), extension))
+    }
+
+    /// write one of the url's files by way of a temporary one
+    fn replace(&self, url: &str, extension: &str, contents: &str) -> io::Result<()> {
+        let tmp = self.path(url, &format!("{}.tmp", extension));
+        fs::write(&tmp, contents)?;
+        fs::rename(tmp, self.path(url, extension))
+    }
+
+    fn write_meta(&self, url: &str, page: &Page, fetched: SystemTime) -> io::Result<Entry> {
+        let entry = Entry {
+            url: url.to_string(),
+            fetched,
+            etag: page.header("etag").map(|h| h.to_string()),
+            last_modified: page.header("last-modified").map(|h| h.to_string()),
+            size: page.body.len() as u64,
+        };
+        let secs = fetched.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut meta = format!(
+            "url={}\nfetched={}\nsize={}\nlocation={}\ncontent_type={}\n",
+            url,
+            secs.as_secs(),
+            entry.size,
+            page.url,
+            page.content_type
+        );
+        for (key, value) in [
+            ("etag", &entry.etag),
+            ("last_modified", &entry.last_modified),
+        ] {
+            if let Some(value) = value {
+                meta.push_str(&format!("{}={}\n", key, value));
+            }
+        }
+        for header in page.headers.iter() {
+            meta.push_str(&format!("header={}\n", header));
+        }
+        self.replace(url, "meta", &meta)?;
+        Ok(entry)
+    }
+}
+
+/// the cached page with the validators a 304 sent in place of its own, as
+/// the site may change them without changing the page
+fn revalidated(mut page: Page, fresh: &Page) -> Page {
+    for name in ["ETag", "Last-Modified"] {
+        if let Some(value) = fresh.header(name) {
+            let header = format!("{}: {}", name, value);
+            let named = |h: &String| {
+                h.split_once(':')
+                    .is_some_and(|(n, _)| n.trim().eq_ignore_ascii_case(name))
+            };
+            match page.headers.iter().position(named) {
+                Some(at) => page.headers[at] = header,
+                None => page.headers.push(header),
+            }
+        }
+    }
+    page
+}
+
+/// the entry and page, without its body, from a `.meta`
+fn parse(meta: &str) -> io::Result<(Entry, Page)> {
+    let mut entry = Entry {
+        url: String::new(),
+        fetched: UNIX_EPOCH,
+        etag: None,
+        last_modified: None,
+        size: 0,
+    };
+    let mut page = Page {
+        url: String::new(),
+        content_type: String::new(),
+        headers: Vec::new(),
+        body: String::new(),
+    };
+    let invalid = |line: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("bad cache entry line {}", line),
+        )
+    };
+    for line in meta.lines() {
+        let (key, value) = line.split_once('=').ok_or_else(|| invalid(line))?;
+        match key {
+            "url" => entry.url = value.to_string(),
+            "fetched" => {
+                let secs = value.parse().map_err(|_| invalid(line))?;
+                entry.fetched = UNIX_EPOCH + Duration::from_secs(secs);
+            }
+            "size" => entry.size = value.parse().map_err(|_| invalid(line))?,
+            "etag" => entry.etag = Some(value.to_string()),
+            "last_modified" => entry.last_modified = Some(value.to_string()),
+            "location" => page.url = value.to_string(),
+            "content_type" => page.content_type = value.to_string(),
+            "header" => page.headers.push(value.to_string()),
+            _ => return Err(invalid(line)),
+        }
+    }
+    if entry.url.is_empty() {
+        return Err(invalid("url missing"));
+    }
+    Ok((entry, page))
+}
+
+/// 64 bit FNV-1a, stable across builds unlike std's hasher
+fn fnv1a(text: &str) -> u64 {
+    text.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fetch::{
+        cache::{revalidated, Cache},
+        fixture::FixtureServer,
+        Page,
+    };
+    use std::time::SystemTime;
+
+    #[test]
+    fn revalidates_with_etag() {
+        let server = FixtureServer::start(&[("/", "<p>First version.</p>")]);
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::open(dir.path()).unwrap();
+        let url = format!("{}/", server.url);
+
+        let before = SystemTime::now();
+        let page = cache.fetch(&url, false).unwrap();
+        assert_eq!(page.body, "<p>First version.</p>");
+        let (entry, cached) = cache.get(&url).unwrap().unwrap();
+        assert_eq!(cached, page);
+        assert_eq!(entry.etag.as_deref(), Some("\"21-0\""));
+        assert_eq!(entry.size, 21);
+        assert!(entry.fetched + std::time::Duration::from_secs(1) >= before);
+
+        // unchanged, a 304 with no body
+        assert_eq!(cache.fetch(&url, false).unwrap(), page);
+        assert_eq!(server.requests("/"), 2);
+
+        server.set("/", "<p>Second version.</p>");
+        let page = cache.fetch(&url, false).unwrap();
+        assert_eq!(page.body, "<p>Second version.</p>");
+        assert_eq!(cache.get(&url).unwrap().unwrap().1, page);
+        assert_eq!(server.requests("/"), 3);
+    }
+
+    #[test]
+    fn keeps_validators_of_304() {
+        let page = Page {
+            url: "http://example.com/".to_string(),
+            content_type: "text/html".to_string(),
+            headers: vec![
+                "Content-Type: text/html".to_string(),
+                "etag: \"old\"".to_string(),
+            ],
+            body: "<p>Page.</p>".to_string(),
+        };
+        let fresh = Page {
+            headers: vec![
+                "ETag: \"new\"".to_string(),
+                "Last-Modified: Mon, 19 Oct 2026 10:00:00 GMT".to_string(),
+            ],
+            body: String::new(),
+            ..page.clone()
+        };
+        let page = revalidated(page, &fresh);
+        assert_eq!(page.header("etag"), Some("\"new\""));
+        assert_eq!(
+            page.header("last-modified"),
+            Some("Mon, 19 Oct 2026 10:00:00 GMT")
+        );
+        assert_eq!(page.headers.len(), 3);
+        assert_eq!(page.body, "<p>Page.</p>");
+        // a 304 without validators keeps the old ones
+        let page = revalidated(
+            page.clone(),
+            &Page {
+                headers: vec![],
+                ..page
+            },
+        );
+        assert_eq!(page.header("etag"), Some("\"new\""));
+    }
+
+    #[test]
+    fn colliding_url_is_a_miss() {
+        let server = FixtureServer::start(&[("/a", "<p>A.</p>")]);
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::open(dir.path()).unwrap();
+        let (a, b) = (format!("{}/a", server.url), format!("{}/b", server.url));
+        cache.fetch(&a, false).unwrap();
+        // as though b hashed to a's files
+        for extension in ["meta", "body"] {
+            std::fs::copy(cache.path(&a, extension), cache.path(&b, extension)).unwrap();
+        }
+        assert!(cache.get(&b).unwrap().is_none());
+        assert!(cache.fetch(&b, true).is_err());
+        assert!(cache.get(&a).unwrap().is_some());
+    }
+
+    #[test]
+    fn offline_only_reads_cache() {
+        let server = FixtureServer::start(&[("/a", "<p>A.</p>"), ("/b", "<p>B.</p>")]);
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::open(dir.path()).unwrap();
+        let (a, b) = (format!("{}/a", server.url), format!("{}/b", server.url));
+
+        assert!(cache.fetch(&a, true).is_err());
+        cache.fetch(&a, false).unwrap();
+        server.set("/a", "<p>Changed.</p>");
+        assert_eq!(cache.fetch(&a, true).unwrap().body, "<p>A.</p>");
+        assert_eq!(server.requests("/a"), 1);
+        assert!(cache.fetch(&b, true).is_err());
+        assert_eq!(server.requests("/b"), 0);
+        assert!(cache
+            .fetch(&format!("{}/missing", server.url), false)
+            .is_err());
+    }
+
+    #[test]
+    fn lists_and_purges() {
+        let server = FixtureServer::start(&[("/a", "<p>A.</p>"), ("/b", "<p>B.</p>")]);
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::open(dir.path()).unwrap();
+        let (a, b) = (format!("{}/a", server.url), format!("{}/b", server.url));
+        cache.fetch(&b, false).unwrap();
+        cache.fetch(&a, false).unwrap();
+
+        let urls = |cache: &Cache| -> Vec<String> {
+            cache
+                .entries()
+                .unwrap()
+                .into_iter()
+                .map(|e| e.url)
+                .collect()
+        };
+        assert_eq!(urls(&cache), vec![a.clone(), b.clone()]);
+        assert_eq!(cache.purge(&[a.as_str()]).unwrap(), 1);
+        assert_eq!(urls(&cache), vec![b.clone()]);
+        assert!(cache.get(&a).unwrap().is_none());
+        cache.fetch(&a, false).unwrap();
+        assert_eq!(cache.purge(&[]).unwrap(), 2);
+        assert!(urls(&cache).is_empty());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,278 @@
+use super::Page;
+use crate::index::html::links;
+use std::{
+    collections::{HashSet, VecDeque},
+    io,
+};
+use url::Url;
+
+/// How far a crawl goes from its seed page.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Crawl {
+    /// links followed from the seed, 0 crawls only the seed
+    pub max_depth: usize,
+    /// pages fetched at most, the seed included
+    pub max_pages: usize,
+}
+
+impl Default for Crawl {
+    fn default() -> Self {
+        Crawl {
+            max_depth: 2,
+            max_pages: 20,
+        }
+    }
+}
+
+/// parse a page limit, a crawl of no pages would have nothing to index
+pub fn parse_max_pages(input: &str) -> Result<usize, String> {
+    input
+        .trim()
+        .parse()
+        .ok()
+        .filter(|pages: &usize| *pages > 0)
+        .ok_or(format!("max pages must be at least 1, got {}", input))
+}
+
+/// A crawled page, the source of the documents indexed from it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Source {
+    pub page: Page,
+    /// links followed from the seed to get here
+    pub depth: usize,
+}
+
+/// What a crawl fetched, in the order it fetched it.
+#[derive(Debug, Default)]
+pub struct Crawled {
+    pub sources: Vec<Source>,
+    /// (url, error) of links that couldn't be fetched
+    pub failed: Vec<(String, String)>,
+}
+
+/// breadth first from the seed, following links to pages on the seed's host
+///
+/// The seed's host is the one it ends up on after redirects, and pages
+/// redirecting off it are dropped. Urls are compared without their
+/// fragment, so a page is fetched once however many links lead to it, and
+/// redirects to a page already crawled are dropped. Fails when the seed
+/// itself can't be fetched or nothing was crawled.
+pub fn crawl<F>(seed: &str, limits: &Crawl, mut fetch: F) -> Result<Crawled, String>
+where
+    F: FnMut(&str) -> io::Result<Page>,
+{
+    let seed = normalize(seed).ok_or(format!("not a url: {}", seed))?;
+    let mut seen: HashSet<String> = HashSet::new();
+    seen.insert(seed.to_string());
+    let mut queue = VecDeque::from([(seed.clone(), 0)]);
+    let mut crawled = Crawled::default();
+    // the seed's url after redirects, once it's fetched
+    let mut site: Option<Url> = None;
+
+    while let Some((url, depth)) = queue.pop_front() {
+        if crawled.sources.len() >= limits.max_pages {
+            break;
+        }
+        let page = match fetch(url.as_str()) {
+            Ok(page) => page,
+            Err(e) if url == seed => return Err(format!("could not fetch {}: {}", url, e)),
+            Err(e) => {
+                crawled.failed.push((url.to_string(), e.to_string()));
+                continue;
+            }
+        };
+        // where a redirect ended up, which may be a page already crawled
+        let landed = normalize(&page.url).unwrap_or_else(|| url.clone());
+        let site = site.get_or_insert_with(|| landed.clone());
+        if !same_site(site, &landed) || (landed != url && !seen.insert(landed.to_string())) {
+            continue;
+        }
+        if depth < limits.max_depth && page.is_html() {
+            for href in links(&page.body).unwrap_or_default() {
+                let link = match landed.join(&href) {
+                    Ok(link) => normalize(link.as_str()),
+                    Err(_) => None,
+                };
+                match link {
+                    Some(link) if same_site(site, &link) && seen.insert(link.to_string()) => {
+                        queue.push_back((link, depth + 1))
+                    }
+                    _ => {}
+                }
+            }
+        }
+        crawled.sources.push(Source { page, depth });
+    }
+    if crawled.sources.is_empty() {
+        return Err(format!("nothing crawled from {}", seed));
+    }
+    Ok(crawled)
+}
+
+/// the url without its fragment, when it's one that can be fetched
+fn normalize(url: &str) -> Option<Url> {
+    let mut url = Url::parse(url).ok()?;
+    url.set_fragment(None);
+    match url.scheme() {
+        "http" | "https" | "file" => Some(url),
+        _ => None,
+    }
+}
+
+/// same scheme, host and port
+fn same_site(a: &Url, b: &Url) -> bool {
+    a.scheme() == b.scheme()
+        && a.host_str() == b.host_str()
+        && a.port_or_known_default() == b.port_or_known_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fetch::{
+        crawl::{crawl, parse_max_pages, Crawl},
+        fetch,
+        fixture::FixtureServer,
+    };
+
+    fn site() -> FixtureServer {
+        FixtureServer::start(&[
+            (
+                "/",
+                r#"<p>Home. <a href="/a">a</a> <a href="b#top">b</a> <a href="/a#again">a</a>
+                <a href="https://example.com/">elsewhere</a> <a href="mailto:x@example.com">mail</a>
+                <a href="/missing">missing</a></p>"#,
+            ),
+            (
+                "/a",
+                r#"<p>Page a. <a href="/c">c</a> <a href="/">home</a></p>"#,
+            ),
+            ("/b", r#"<p>Page b. <a href="/c">c</a></p>"#),
+            ("/c", r#"<p>Page c. <a href="/d">d</a></p>"#),
+            ("/d", "<p>Page d.</p>"),
+        ])
+    }
+
+    fn paths(server: &FixtureServer, limits: &Crawl) -> Vec<(String, usize)> {
+        let crawled = crawl(&server.url, limits, fetch).unwrap();
+        crawled
+            .sources
+            .iter()
+            .map(|s| (s.page.url.replacen(&server.url, "", 1), s.depth))
+            .collect()
+    }
+
+    fn path(path: &str, depth: usize) -> (String, usize) {
+        (path.to_string(), depth)
+    }
+
+    #[test]
+    fn crawls_same_site_breadth_first() {
+        let server = site();
+        let limits = Crawl {
+            max_depth: 5,
+            max_pages: 100,
+        };
+        let crawled = crawl(&server.url, &limits, fetch).unwrap();
+        assert_eq!(crawled.failed.len(), 1);
+        assert!(crawled.failed[0].0.ends_with("/missing"));
+        assert_eq!(
+            paths(&server, &limits),
+            vec![
+                path("/", 0),
+                path("/a", 1),
+                path("/b", 1),
+                path("/c", 2),
+                path("/d", 3)
+            ]
+        );
+        // once per crawl, however many links lead there
+        for p in ["/", "/a", "/b", "/c", "/d"] {
+            assert_eq!(server.requests(p), 2, "{}", p);
+        }
+    }
+
+    #[test]
+    fn stops_at_limits() {
+        let server = site();
+        let depth = Crawl {
+            max_depth: 1,
+            max_pages: 100,
+        };
+        assert_eq!(
+            paths(&server, &depth),
+            vec![path("/", 0), path("/a", 1), path("/b", 1)]
+        );
+        let pages = Crawl {
+            max_depth: 5,
+            max_pages: 2,
+        };
+        assert_eq!(paths(&server, &pages), vec![path("/", 0), path("/a", 1)]);
+        let seed_only = Crawl {
+            max_depth: 0,
+            max_pages: 100,
+        };
+        assert_eq!(paths(&server, &seed_only), vec![path("/", 0)]);
+        assert_eq!(server.requests("/c"), 0);
+    }
+
+    #[test]
+    fn crawls_at_least_one_page() {
+        let server = site();
+        let none = Crawl {
+            max_depth: 5,
+            max_pages: 0,
+        };
+        assert!(crawl(&server.url, &none, fetch).is_err());
+        assert_eq!(server.requests("/"), 0);
+        assert_eq!(parse_max_pages("3"), Ok(3));
+        assert!(parse_max_pages("0").is_err());
+        assert!(parse_max_pages("-1").is_err());
+    }
+
+    #[test]
+    fn follows_seed_redirect() {
+        let old = FixtureServer::start(&[
+            ("/y", "<p>Left behind.</p>"),
+            ("/z", "<p>Redirected back.</p>"),
+        ]);
+        let new = FixtureServer::start(&[
+            (
+                "/home",
+                &format!(
+                    r#"<p>Moved. <a href="x">x</a> <a href="/away">away</a>
+                    <a href="{}/y">y</a></p>"#,
+                    old.url
+                ),
+            ),
+            ("/x", "<p>Page x.</p>"),
+        ]);
+        old.redirect("/", &format!("{}/home", new.url));
+        new.redirect("/away", &format!("{}/z", old.url));
+
+        let crawled = crawl(&old.url, &Crawl::default(), fetch).unwrap();
+        let urls: Vec<(String, usize)> = crawled
+            .sources
+            .iter()
+            .map(|s| (s.page.url.clone(), s.depth))
+            .collect();
+        // links are followed on the site the seed moved to, and the page
+        // redirecting back off it is dropped
+        assert_eq!(
+            urls,
+            vec![
+                (format!("{}/home", new.url), 0),
+                (format!("{}/x", new.url), 1)
+            ]
+        );
+        assert_eq!(new.requests("/away"), 1);
+        assert_eq!(old.requests("/y"), 0);
+    }
+
+    #[test]
+    fn fails_without_seed() {
+        let server = site();
+        let missing = format!("{}/missing", server.url);
+        assert!(crawl(&missing, &Crawl::default(), fetch).is_err());
+        assert!(crawl("not a url", &Crawl::default(), fetch).is_err());
+    }
+}
@@ -0,0 +1,110 @@
+//! A local http server for testing fetching without the network.
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Write},
+    net::TcpListener,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+/// Serves html pages by path, counting requests for each.
+///
+/// Every page has an etag of its length and version, and a request
+/// whose `If-None-Match` matches it gets a 304 without the body. Redirected
+/// paths get a 302 to wherever they were redirected to.
+pub struct FixtureServer {
+    pub url: String,
+    /// path -> (version, body)
+    pages: Arc<Mutex<HashMap<String, (usize, String)>>>,
+    /// path -> location
+    redirects: Arc<Mutex<HashMap<String, String>>>,
+    requests: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+impl FixtureServer {
+    pub fn start(pages: &[(&str, &str)]) -> FixtureServer {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = FixtureServer {
+            url: format!("http://{}", listener.local_addr().unwrap()),
+            pages: Arc::new(Mutex::new(
+                pages
+                    .iter()
+                    .map(|(path, body)| (path.to_string(), (0, body.to_string())))
+                    .collect(),
+            )),
+            redirects: Arc::new(Mutex::new(HashMap::new())),
+            requests: Arc::new(Mutex::new(HashMap::new())),
+        };
+        let (pages, redirects, requests) = (
+            server.pages.clone(),
+            server.redirects.clone(),
+            server.requests.clone(),
+        );
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let path = line.split_whitespace().nth(1).unwrap_or("/").to_string();
+                let mut if_none_match = None;
+                loop {
+                    let mut header = String::new();
+                    if reader.read_line(&mut header).unwrap() <= 2 {
+                        break;
+                    }
+                    if let Some((name, value)) = header.split_once(':') {
+                        if name.eq_ignore_ascii_case("if-none-match") {
+                            if_none_match = Some(value.trim().to_string());
+                        }
+                    }
+                }
+                *requests.lock().unwrap().entry(path.clone()).or_insert(0) += 1;
+                let redirect = redirects.lock().unwrap().get(&path).cloned();
+                let response = match (redirect, pages.lock().unwrap().get(&path)) {
+                    (Some(location), _) => format!(
+                        "HTTP/1.1 302 Found\r\nLocation: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                        location
+                    ),
+                    (None, Some((version, body))) => {
+                        let etag = format!("\"{}-{}\"", body.len(), version);
+                        if if_none_match.as_ref() == Some(&etag) {
+                            format!("HTTP/1.1 304 Not Modified\r\nETag: {}\r\nConnection: close\r\n\r\n", etag)
+                        } else {
+                            format!(
+                                "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nETag: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                                etag,
+                                body.len(),
+                                body
+                            )
+                        }
+                    }
+                    (None, None) => {
+                        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                            .to_string()
+                    }
+                };
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        server
+    }
+
+    /// change what's served at the path, and so its etag
+    pub fn set(&self, path: &str, body: &str) {
+        let mut pages = self.pages.lock().unwrap();
+        let version = pages.get(path).map_or(0, |(v, _)| v + 1);
+        pages.insert(path.to_string(), (version, body.to_string()));
+    }
+
+    /// redirect the path to a location, a path or another server's url
+    pub fn redirect(&self, path: &str, location: &str) {
+        let mut redirects = self.redirects.lock().unwrap();
+        redirects.insert(path.to_string(), location.to_string());
+    }
+
+    /// requests made for the path so far
+    pub fn requests(&self, path: &str) -> usize {
+        *self.requests.lock().unwrap().get(path).unwrap_or(&0)
+    }
+}
@@ -0,0 +1,93 @@
+use curl::easy::{Easy, List};
+use std::{io, time::Duration};
+
+pub mod cache;
+pub mod crawl;
+#[cfg(test)]
+mod fixture;
+
+/// A fetched page, what's needed of it to index it and follow its links.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page {
+    /// where the page ended up after redirects
+    pub url: String,
+    pub content_type: String,
+    /// response headers as `Name: value` lines
+    pub headers: Vec<String>,
+    pub body: String,
+}
+
+impl Page {
+    /// whether the page should be parsed as html rather than taken as text
+    pub fn is_html(&self) -> bool {
+        self.content_type.contains("html") || self.body.trim_start().starts_with('<')
+    }
+
+    /// value of the first header with this name, names compared case insensitively
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find_map(|h| {
+            let (n, value) = h.split_once(':')?;
+            match n.trim().eq_ignore_ascii_case(name) {
+                true => Some(value.trim()),
+                false => None,
+            }
+        })
+    }
+}
+
+/// download a page, following redirects
+///
+/// Error responses are errors, not pages.
+pub fn fetch(url: &str) -> io::Result<Page> {
+    let (status, page) = request(url, &[])?;
+    if status >= 400 {
+        return Err(io::Error::other(format!("{} responded {}", url, status)));
+    }
+    Ok(page)
+}
+
+/// get a page sending extra request headers, returning the status with it
+///
+/// Non http urls like `file://` have a status of 0.
+fn request(url: &str, headers: &[String]) -> io::Result<(u32, Page)> {
+    let mut handle = Easy::new();
+    handle.follow_location(true)?;
+    handle.max_redirections(5)?;
+    handle.timeout(Duration::from_secs(10))?;
+    handle.useragent(concat!("vboo/", env!("CARGO_PKG_VERSION")))?;
+    let mut list = List::new();
+    for header in headers {
+        list.append(header)?;
+    }
+    handle.http_headers(list)?;
+    handle.url(url)?;
+
+    let mut response_headers = Vec::new();
+    let mut body = Vec::new();
+    {
+        let mut transfer = handle.transfer();
+        transfer.header_function(|line| {
+            let line = String::from_utf8_lossy(line).trim().to_string();
+            // each redirect starts over
+            if line.starts_with("HTTP/") {
+                response_headers.clear();
+            } else if !line.is_empty() {
+                response_headers.push(line);
+            }
+            true
+        })?;
+        transfer.write_function(|data| {
+            body.extend_from_slice(data);
+            Ok(data.len())
+        })?;
+        transfer.perform()?;
+    }
+
+    let page = Page {
+        url: handle.effective_url()?.unwrap_or(url).to_string(),
+        content_type: handle.content_type()?.unwrap_or("").to_string(),
+        headers: response_headers,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    };
+    Ok((handle.response_code()?, page))
+}
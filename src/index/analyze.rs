@@ -0,0 +1,33 @@
+use rust_stemmers::Stemmer;
+use std::collections::HashMap;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// lowercased and stemmed, how both documents and queries are matched
+pub fn stem(word: &str, stemmer: &Stemmer) -> String {
+    stemmer.stem(word.to_lowercase().as_str()).to_string()
+}
+
+/// stemmed terms of text with their frequencies, in order of first appearance
+pub fn term_freqs(text: &str, stemmer: &Stemmer) -> Vec<(String, usize)> {
+    //-------------------------------------term  , position
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    let mut freqs: Vec<(String, usize)> = Vec::new();
+    for word in text.unicode_words() {
+        let sword = stem(word, stemmer);
+        match positions.get(&sword) {
+            Some(pos) => freqs[*pos].1 += 1,
+            None => {
+                positions.insert(sword.clone(), freqs.len());
+                freqs.push((sword, 1));
+            }
+        }
+    }
+    freqs
+}
+
+/// sentences of text, trimmed, skipping empty ones
+pub fn sentences(text: &str) -> impl Iterator<Item = &str> {
+    text.unicode_sentences()
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+}
@@ -0,0 +1,35 @@
+use std::ops::Range;
+
+/// doc ids of the doc and up to `n` docs either side of it from the same source
+///
+/// Documents are indexed in the order they're extracted in, a page's
+/// sentences one after another, so a doc's neighbours by id are the
+/// sentences around it as long as they share its source.
+pub fn context(doc_id: usize, sources: &[usize], n: usize) -> Range<usize> {
+    let source = sources[doc_id];
+    let start = (doc_id.saturating_sub(n)..doc_id)
+        .rev()
+        .take_while(|d| sources[*d] == source)
+        .last()
+        .unwrap_or(doc_id);
+    let end = (doc_id + 1..sources.len().min(doc_id + n + 1))
+        .take_while(|d| sources[*d] == source)
+        .last()
+        .unwrap_or(doc_id);
+    start..end + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::index::context::context;
+
+    #[test]
+    fn stays_within_source() {
+        let sources = [0, 0, 0, 0, 1, 1, 2];
+        assert_eq!(context(2, &sources, 1), 1..4);
+        assert_eq!(context(2, &sources, 5), 0..4);
+        assert_eq!(context(4, &sources, 2), 4..6);
+        assert_eq!(context(6, &sources, 2), 6..7);
+        assert_eq!(context(0, &sources, 0), 0..1);
+    }
+}
@@ -0,0 +1,314 @@
+use crate::ranking::rank_result::RankResult;
+use ndarray::{Array2, ArrayView2, Axis};
+use std::collections::{HashMap, HashSet};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// How near-duplicates are found, by MinHash over word shingles with
+/// locality sensitive hashing to only compare likely pairs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Dedup {
+    /// words per shingle
+    pub shingle: usize,
+    /// minhashes in a document's signature, a multiple of bands
+    pub hashes: usize,
+    /// signature bands, documents agreeing on all of one band's rows are compared
+    pub bands: usize,
+    /// estimated jaccard similarity at or above which documents are duplicates
+    pub threshold: f32,
+}
+
+impl Default for Dedup {
+    fn default() -> Self {
+        Dedup {
+            shingle: 3,
+            hashes: 128,
+            bands: 32,
+            threshold: 0.8,
+        }
+    }
+}
+
+impl Dedup {
+    /// minhash signature of the text's shingles
+    ///
+    /// Each of the hashes takes the minimum over the shingles of their hash
+    /// mixed with its own seed, so two signatures agree at a position with
+    /// probability the jaccard similarity of the shingle sets.
+    pub fn signature(&self, text: &str) -> Vec<u64> {
+        let shingles = shingles(text, self.shingle);
+        (0..self.hashes as u64)
+            .map(|i| {
+                let seed = mix(i);
+                shingles
+                    .iter()
+                    .map(|s| mix(s ^ seed))
+                    .min()
+                    .unwrap_or(u64::MAX)
+            })
+            .collect()
+    }
+}
+
+/// hashes of the text's lowercased word `k`-grams, the whole text being one
+/// when it's shorter than that
+pub fn shingles(text: &str, k: usize) -> HashSet<u64> {
+    let words: Vec<String> = text.unicode_words().map(|w| w.to_lowercase()).collect();
+    if words.is_empty() {
+        return HashSet::new();
+    }
+    words
+        .windows(k.clamp(1, words.len()))
+        .map(|w| fnv1a(&w.join(" ")))
+        .collect()
+}
+
+/// fraction of positions two signatures agree at, estimating the jaccard
+/// similarity of what they were taken from
+pub fn similarity(a: &[u64], b: &[u64]) -> f32 {
+    let same = a.iter().zip(b.iter()).filter(|(x, y)| x == y).count();
+    same as f32 / a.len().max(1) as f32
+}
+
+/// Groups of near-duplicate documents, each represented by its first document.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Duplicates {
+    /// doc id -> the doc id representing its group
+    representatives: Vec<usize>,
+    /// representative -> documents in its group, itself included
+    sizes: HashMap<usize, usize>,
+}
+
+impl Duplicates {
+    /// group the documents, duplicates of duplicates being in the same group
+    ///
+    /// Empty documents have no shingles and are never duplicates.
+    pub fn find(texts: &[&str], dedup: &Dedup) -> Duplicates {
+        let signatures: Vec<Vec<u64>> = texts.iter().map(|t| dedup.signature(t)).collect();
+        let rows = dedup.hashes / dedup.bands.max(1);
+        let mut groups = UnionFind::new(texts.len());
+        for band in 0..dedup.bands {
+            let mut buckets: HashMap<&[u64], Vec<usize>> = HashMap::new();
+            for (doc, signature) in signatures.iter().enumerate() {
+                let rows = &signature[band * rows..(band + 1) * rows];
+                if rows.iter().all(|h| *h == u64::MAX) {
+                    continue;
+                }
+                buckets.entry(rows).or_default().push(doc);
+            }
+            for docs in buckets.values() {
+                for (i, a) in docs.iter().enumerate() {
+                    for b in docs[i + 1..].iter() {
+                        if groups.find(*a) != groups.find(*b)
+                            && similarity(&signatures[*a], &signatures[*b]) >= dedup.threshold
+                        {
+                            groups.union(*a, *b);
+                        }
+                    }
+                }
+            }
+        }
+
+        let representatives: Vec<usize> = (0..texts.len()).map(|d| groups.find(d)).collect();
+        let mut sizes = HashMap::new();
+        for r in representatives.iter() {
+            *sizes.entry(*r).or_insert(0) += 1;
+        }
+        Duplicates {
+            representatives,
+            sizes,
+        }
+    }
+
+    /// the document standing for the doc's group, the first of it
+    pub fn representative(&self, doc_id: usize) -> usize {
+        self.representatives[doc_id]
+    }
+
+    /// other documents near-duplicating the doc
+    pub fn count(&self, doc_id: usize) -> usize {
+        self.sizes[&self.representative(doc_id)] - 1
+    }
+
+    /// documents that duplicate an earlier one
+    pub fn num_duplicates(&self) -> usize {
+        self.representatives.len() - self.sizes.len()
+    }
+
+    /// the document x term matrix with every document but the first of its
+    /// group emptied
+    ///
+    /// An empty document scores 0 by either op and is never ranked, so
+    /// ranking this collapses results as they're collected and the top k are
+    /// k different groups, each standing in for its near-duplicates.
+    pub fn without_duplicates(&self, dt_matrix: &ArrayView2<f32>) -> Array2<f32> {
+        let mut deduped = dt_matrix.to_owned();
+        for (doc_id, mut doc) in deduped.axis_iter_mut(Axis(0)).enumerate() {
+            if self.representative(doc_id) != doc_id {
+                doc.fill(0.0);
+            }
+        }
+        deduped
+    }
+
+    /// results with only the best ranked of each group kept, in their order
+    pub fn collapse(&self, results: &[RankResult]) -> Vec<RankResult> {
+        let mut seen = HashSet::new();
+        results
+            .iter()
+            .filter(|r| seen.insert(self.representative(r.doc_id)))
+            .copied()
+            .collect()
+    }
+}
+
+/// Disjoint sets whose roots are their smallest member.
+struct UnionFind {
+    parents: Vec<usize>,
+}
+
+impl UnionFind {
+    fn new(n: usize) -> UnionFind {
+        UnionFind {
+            parents: (0..n).collect(),
+        }
+    }
+
+    fn find(&mut self, x: usize) -> usize {
+        let mut root = x;
+        while self.parents[root] != root {
+            root = self.parents[root];
+        }
+        // point the path straight at the root
+        let mut x = x;
+        while self.parents[x] != root {
+            let next = self.parents[x];
+            self.parents[x] = root;
+            x = next;
+        }
+        root
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        let (root, child) = (a.min(b), a.max(b));
+        self.parents[child] = root;
+    }
+}
+
+fn fnv1a(text: &str) -> u64 {
+    text.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// splitmix64's finalizer, spreading seeds and hashes over all bits
+fn mix(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{array, Array2};
+
+    use crate::{
+        index::dedup::{shingles, similarity, Dedup, Duplicates},
+        ranking::{
+            op::Op,
+            rank::{rank_parallel, K},
+            rank_result::RankResult,
+        },
+    };
+
+    static TEXTS: [&str; 6] = [
+        "Rust is a company-backed language with great support from the community.",
+        "Get started with Rust today.",
+        "rust is a company backed language with great support from the community",
+        "Get started with Rust today.",
+        "The Spanish square was empty when the river rose.",
+        "Rust is a company-backed language with great support from its community!",
+    ];
+
+    #[test]
+    fn estimates_similarity() {
+        let dedup = Dedup::default();
+        let a = dedup.signature(TEXTS[0]);
+        assert_eq!(similarity(&a, &dedup.signature(TEXTS[2])), 1.0);
+        assert!(similarity(&a, &dedup.signature(TEXTS[4])) < 0.1);
+        // 8 of 10 shingles are shared, a jaccard similarity of 8/12
+        let close = similarity(&a, &dedup.signature(TEXTS[5]));
+        assert!((close - 8.0 / 12.0).abs() < 0.15, "{}", close);
+
+        assert_eq!(shingles("one two", 3).len(), 1);
+        assert!(shingles(" ,. ", 3).is_empty());
+    }
+
+    #[test]
+    fn groups_near_duplicates() {
+        let dedup = Dedup {
+            threshold: 0.6,
+            ..Dedup::default()
+        };
+        let duplicates = Duplicates::find(&TEXTS, &dedup);
+        let representatives: Vec<usize> = (0..TEXTS.len())
+            .map(|d| duplicates.representative(d))
+            .collect();
+        assert_eq!(representatives, vec![0, 1, 0, 1, 4, 0]);
+        assert_eq!(duplicates.count(5), 2);
+        assert_eq!(duplicates.count(4), 0);
+        assert_eq!(duplicates.num_duplicates(), 3);
+
+        let strict = Duplicates::find(&TEXTS, &Dedup::default());
+        assert_eq!(strict.representative(2), 0);
+        assert_eq!(strict.representative(5), 5);
+    }
+
+    #[test]
+    fn collapses_to_best_ranked() {
+        let duplicates = Duplicates::find(&TEXTS, &Dedup::default());
+        let results: Vec<RankResult> = [(2, 0.9), (4, 0.8), (0, 0.7), (3, 0.6), (1, 0.5)]
+            .iter()
+            .map(|(doc_id, score)| RankResult {
+                doc_id: *doc_id,
+                score: *score,
+            })
+            .collect();
+        let collapsed: Vec<usize> = duplicates
+            .collapse(&results)
+            .iter()
+            .map(|r| r.doc_id)
+            .collect();
+        assert_eq!(collapsed, vec![2, 4, 3]);
+    }
+
+    #[test]
+    fn collapsed_ranking_keeps_k_results() {
+        // more copies of the best matching doc than there are results
+        let distinct: Vec<String> = (0..K)
+            .map(|i| format!("doc{} alpha{} beta{} gamma{}", i, i, i, i))
+            .collect();
+        let mut texts = vec!["Get started with Rust today."; K + 2];
+        texts.extend(distinct.iter().map(|t| t.as_str()));
+        let duplicates = Duplicates::find(&texts, &Dedup::default());
+        assert_eq!(duplicates.num_duplicates(), K + 1);
+        let dtm = Array2::from_shape_fn((texts.len(), 2), |(doc_id, term)| match term {
+            0 if doc_id < K + 2 => 0.9,
+            0 => 0.5,
+            _ => 0.1,
+        });
+        let query = array![1.0, 0.0];
+
+        let (results, _) = rank_parallel(&query.view(), &dtm.view(), &Op::OR, 2.0);
+        assert_eq!(duplicates.collapse(&results).len(), 1);
+
+        let deduped = duplicates.without_duplicates(&dtm.view());
+        for op in [Op::OR, Op::AND] {
+            let (results, _) = rank_parallel(&query.view(), &deduped.view(), &op, 2.0);
+            assert_eq!(results.len(), K);
+            assert_eq!(duplicates.collapse(&results), results);
+            assert_eq!(results[0].doc_id, 0);
+        }
+    }
+}
@@ -0,0 +1,293 @@
+use super::{analyze::sentences, incremental::IncrementalIndex};
+use crate::ranking::{normalization::Normalization, scorer::Scorer};
+use ndarray::{concatenate, Array2, ArrayView2, Axis};
+use std::{fmt, str::FromStr};
+
+/// A named part of a document, indexed on its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Field {
+    /// title of the page the document is from
+    Title,
+    /// headings of the section the document is in
+    Headings,
+    /// the document's own text
+    Body,
+    /// link and image alt text within the document
+    Anchors,
+    /// description and keywords of the page the document is from
+    Meta,
+}
+
+impl Field {
+    /// every field, in the order their columns are laid out in
+    pub const ALL: [Field; 5] = [
+        Field::Title,
+        Field::Headings,
+        Field::Body,
+        Field::Anchors,
+        Field::Meta,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Field::Title => "title",
+            Field::Headings => "headings",
+            Field::Body => "body",
+            Field::Anchors => "anchors",
+            Field::Meta => "meta",
+        }
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Field {
+    type Err = String;
+    fn from_str(field: &str) -> Result<Self, Self::Err> {
+        match field.to_lowercase().as_str() {
+            "title" => Ok(Field::Title),
+            "headings" | "heading" => Ok(Field::Headings),
+            "body" => Ok(Field::Body),
+            "anchors" | "anchor" => Ok(Field::Anchors),
+            "meta" => Ok(Field::Meta),
+            _ => Err(format!("unsupported field {}", field)),
+        }
+    }
+}
+
+/// Multiplier of each field's term weights.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Boosts([f32; 5]);
+
+impl Default for Boosts {
+    fn default() -> Self {
+        Boosts([1.0; 5])
+    }
+}
+
+impl Boosts {
+    pub fn get(&self, field: Field) -> f32 {
+        self.0[field as usize]
+    }
+
+    pub fn set(&mut self, field: Field, boost: f32) {
+        self.0[field as usize] = boost;
+    }
+}
+
+/// parses `title=3,headings=2`, any field left out keeps a boost of 1
+impl FromStr for Boosts {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Boosts, Self::Err> {
+        let mut boosts = Boosts::default();
+        for part in input.split(',').map(|p| p.trim()).filter(|p| !p.is_empty()) {
+            let (field, boost) = part
+                .split_once('=')
+                .ok_or(format!("expected field=boost, got {}", part))?;
+            let field = field.trim();
+            let boost = boost
+                .trim()
+                .parse()
+                .ok()
+                .filter(|b: &f32| b.is_finite() && *b >= 0.0)
+                .ok_or(format!(
+                    "boost for {} must be a finite non-negative number, got {}",
+                    field, boost
+                ))?;
+            boosts.set(field.parse()?, boost);
+        }
+        Ok(boosts)
+    }
+}
+
+/// A document with its text split into fields.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Document {
+    pub title: String,
+    pub headings: String,
+    pub body: String,
+    pub anchors: String,
+    pub meta: String,
+}
+
+impl Document {
+    /// a document with only body text
+    pub fn body(body: &str) -> Document {
+        Document {
+            body: body.to_string(),
+            ..Document::default()
+        }
+    }
+
+    /// every sentence of plain text as its own document
+    pub fn from_text(text: &str) -> Vec<Document> {
+        sentences(text).map(Document::body).collect()
+    }
+
+    pub fn field(&self, field: Field) -> &str {
+        match field {
+            Field::Title => &self.title,
+            Field::Headings => &self.headings,
+            Field::Body => &self.body,
+            Field::Anchors => &self.anchors,
+            Field::Meta => &self.meta,
+        }
+    }
+}
+
+/// An index per field, sharing doc ids.
+///
+/// Each field has its own vocabulary and collection statistics, so a term
+/// rare in headings but common in bodies is weighted higher as a heading.
+/// The combined term space lays the fields' term spaces side by side in
+/// `Field::ALL` order.
+pub struct FieldedIndex {
+    indexes: Vec<IncrementalIndex>,
+}
+
+impl FieldedIndex {
+    pub fn new(scorer: Scorer, normalization: Normalization) -> FieldedIndex {
+        FieldedIndex {
+            indexes: Field::ALL
+                .iter()
+                .map(|_| IncrementalIndex::new(scorer, normalization))
+                .collect(),
+        }
+    }
+
+    pub fn num_docs(&self) -> usize {
+        self.indexes[0].num_docs()
+    }
+
+    /// terms across every field's term space
+    pub fn num_terms(&self) -> usize {
+        self.indexes.iter().map(|i| i.num_terms()).sum()
+    }
+
+    pub fn field(&self, field: Field) -> &IncrementalIndex {
+        &self.indexes[field as usize]
+    }
+
+    /// where the field's term space starts in the combined one
+    pub fn offset(&self, field: Field) -> usize {
+        self.indexes[..field as usize]
+            .iter()
+            .map(|i| i.num_terms())
+            .sum()
+    }
+
+    /// index every field of the document, returning its doc id
+    pub fn add(&mut self, doc: &Document) -> usize {
+        let mut doc_id = 0;
+        for (field, index) in Field::ALL.iter().zip(self.indexes.iter_mut()) {
+            doc_id = index.add(doc.field(*field));
+        }
+        doc_id
+    }
+
+    pub fn refresh(&mut self) {
+        for index in self.indexes.iter_mut() {
+            index.refresh();
+        }
+    }
+
+    /// document x combined term weights
+    pub fn weights(&self) -> Array2<f32> {
+        let views: Vec<ArrayView2<f32>> = self.indexes.iter().map(|i| i.weights()).collect();
+        concatenate(Axis(1), &views).expect("fields share doc ids")
+    }
+
+    /// stem a word the same way documents are
+    pub fn stem(&self, word: &str) -> String {
+        self.indexes[0].stem(word)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        index::fields::{Boosts, Document, Field, FieldedIndex},
+        ranking::{normalization::Normalization, scorer::Scorer},
+    };
+
+    #[test]
+    fn fields_indexed_separately() {
+        let mut index = FieldedIndex::new(Scorer::TFIDF, Normalization::None);
+        let docs = [
+            Document {
+                title: "Rust".to_string(),
+                headings: "Why Rust".to_string(),
+                body: "It is fast.".to_string(),
+                ..Document::default()
+            },
+            Document {
+                title: "Rust".to_string(),
+                headings: "Community".to_string(),
+                body: "Rust has a friendly community.".to_string(),
+                anchors: "forum".to_string(),
+                ..Document::default()
+            },
+            Document {
+                title: "Rust".to_string(),
+                body: "Nothing about it here.".to_string(),
+                ..Document::default()
+            },
+        ];
+        for doc in docs.iter() {
+            index.add(doc);
+        }
+        index.refresh();
+        assert_eq!(index.num_docs(), 3);
+
+        let title = index.field(Field::Title);
+        let body = index.field(Field::Body);
+        let rust = title.term_id("rust").unwrap();
+        // in every doc's title, so no weight as a title term
+        assert_eq!(title.weights()[[0, rust]], 0.0);
+        let body_rust = body.term_id("rust").unwrap();
+        assert!(body.weights()[[1, body_rust]] > 0.0);
+
+        let weights = index.weights();
+        assert_eq!(weights.ncols(), index.num_terms());
+        let column = index.offset(Field::Body) + body_rust;
+        assert_eq!(weights[[1, column]], body.weights()[[1, body_rust]]);
+        assert_eq!(index.offset(Field::Title), 0);
+        assert_eq!(
+            index.offset(Field::Meta),
+            index.num_terms() - index.field(Field::Meta).num_terms()
+        );
+    }
+
+    #[test]
+    fn parses_field_names() {
+        for field in Field::ALL {
+            assert_eq!(field.name().parse::<Field>(), Ok(field));
+        }
+        assert_eq!("Heading".parse::<Field>(), Ok(Field::Headings));
+        assert!("footer".parse::<Field>().is_err());
+    }
+
+    #[test]
+    fn parses_boosts() {
+        let boosts: Boosts = "title=3, headings=1.5".parse().unwrap();
+        assert_eq!(boosts.get(Field::Title), 3.0);
+        assert_eq!(boosts.get(Field::Headings), 1.5);
+        assert_eq!(boosts.get(Field::Body), 1.0);
+        assert!("title".parse::<Boosts>().is_err());
+        assert!("footer=2".parse::<Boosts>().is_err());
+        assert!("title=big".parse::<Boosts>().is_err());
+        assert!("title=-1".parse::<Boosts>().is_err());
+        assert!("title=inf".parse::<Boosts>().is_err());
+        assert!("title=NaN".parse::<Boosts>().is_err());
+        assert!("anchors=-2"
+            .parse::<Boosts>()
+            .unwrap_err()
+            .contains("anchors"));
+        assert_eq!("meta=0".parse::<Boosts>().unwrap().get(Field::Meta), 0.0);
+    }
+}
@@ -0,0 +1,352 @@
+use super::{analyze::sentences, fields::Document};
+
+/// words that start a chapter-like heading, followed by its number
+static DIVISIONS: [&str; 8] = [
+    "chapter", "book", "part", "volume", "stave", "act", "scene", "letter",
+];
+
+/// numbers written out, as some books number their chapters
+static NUMBERS: [&str; 25] = [
+    "one", "two", "three", "four", "five", "six", "seven", "eight", "nine", "ten", "eleven",
+    "twelve", "first", "second", "third", "fourth", "fifth", "sixth", "seventh", "eighth", "ninth",
+    "tenth", "eleventh", "twelfth", "last",
+];
+
+/// whether text is a Project Gutenberg ebook, with its license header
+pub fn is_gutenberg(text: &str) -> bool {
+    text.lines().any(|l| marker(l, "START"))
+}
+
+/// split a Project Gutenberg ebook into documents, one per sentence of its text
+///
+/// The license header and footer around the `*** START OF` and `*** END OF`
+/// markers are left out, though the header's title and author are kept as
+/// every document's title and meta. Hard wrapped lines are joined back into
+/// paragraphs, so sentences aren't cut at line ends, and each document gets
+/// the chapter it's in as its headings. Chapter headings are documents too,
+/// but a table of contents, a `CONTENTS` line and the blocks listing chapter
+/// headings a line each after it, is left out.
+pub fn extract(text: &str) -> Vec<Document> {
+    let (header, content) = strip_boilerplate(text);
+    let field = |name: &str| {
+        header
+            .lines()
+            .find_map(|l| l.trim().strip_prefix(name))
+            .map(|v| v.trim().to_string())
+            .unwrap_or_default()
+    };
+    let (title, author) = (field("Title:"), field("Author:"));
+
+    let mut docs = Vec::new();
+    let mut chapter = String::new();
+    let mut blocks = blocks(content).into_iter().peekable();
+    while let Some(lines) = blocks.next() {
+        let paragraph = lines.join(" ");
+        if is_contents(&paragraph) {
+            // a heading of its own is the first chapter, not part of the listing
+            while blocks
+                .next_if(|b| b.len() > 1 && b.iter().all(|l| is_chapter(l)))
+                .is_some()
+            {}
+            continue;
+        }
+        // headings are lines of their own, unlike a table of contents listing them
+        if lines.len() == 1 && is_chapter(&paragraph) {
+            chapter = paragraph;
+            // a title on its own line under the number, like `CHAPTER I.` then `THE RIVER`
+            if let Some(name) = blocks.next_if(|b| b.len() == 1 && is_title(b[0])) {
+                chapter = format!("{} {}", chapter, name[0]);
+            }
+            docs.push(Document {
+                title: title.clone(),
+                headings: chapter.clone(),
+                body: chapter.clone(),
+                meta: author.clone(),
+                ..Document::default()
+            });
+            continue;
+        }
+        for sentence in sentences(&paragraph) {
+            docs.push(Document {
+                title: title.clone(),
+                headings: chapter.clone(),
+                body: sentence.to_string(),
+                meta: author.clone(),
+                ..Document::default()
+            });
+        }
+    }
+    docs
+}
+
+/// (header, content) of an ebook, the footer dropped
+///
+/// Text without the markers is all content.
+pub fn strip_boilerplate(text: &str) -> (&str, &str) {
+    let mut header = "";
+    let mut content = text;
+    if let Some(start) = line_with(content, |l| marker(l, "START")) {
+        header = &content[..start.0];
+        content = &content[start.1..];
+    }
+    if let Some(end) = line_with(content, |l| marker(l, "END")) {
+        content = &content[..end.0];
+        // older ebooks close with a line of their own before the marker
+        if let Some(last) = line_with(content, |l| {
+            l.trim_start()
+                .to_lowercase()
+                .starts_with("end of the project gutenberg")
+                || l.trim_start()
+                    .to_lowercase()
+                    .starts_with("end of project gutenberg")
+        }) {
+            content = &content[..last.0];
+        }
+    }
+    (header, content)
+}
+
+/// lines joined into paragraphs, which are separated by blank lines
+pub fn paragraphs(text: &str) -> Vec<String> {
+    blocks(text).iter().map(|lines| lines.join(" ")).collect()
+}
+
+/// trimmed lines of each paragraph
+fn blocks(text: &str) -> Vec<Vec<&str>> {
+    let mut blocks = Vec::new();
+    let mut current = Vec::new();
+    for line in text.lines().map(|l| l.trim()) {
+        if !line.is_empty() {
+            current.push(line);
+        } else if !current.is_empty() {
+            blocks.push(std::mem::take(&mut current));
+        }
+    }
+    if !current.is_empty() {
+        blocks.push(current);
+    }
+    blocks
+}
+
+/// `*** START OF THE PROJECT GUTENBERG EBOOK ...` or its `END` counterpart
+fn marker(line: &str, which: &str) -> bool {
+    let line = line.trim();
+    let rest = line.trim_start_matches('*').trim_start().to_uppercase();
+    line.starts_with("***")
+        && rest.starts_with(&format!("{} OF", which))
+        && rest.contains("PROJECT GUTENBERG")
+}
+
+/// (start, end) byte offsets of the first line matching, the end past its newline
+fn line_with<F: Fn(&str) -> bool>(text: &str, matches: F) -> Option<(usize, usize)> {
+    let mut start = 0;
+    for line in text.split_inclusive('\n') {
+        if matches(line) {
+            return Some((start, start + line.len()));
+        }
+        start += line.len();
+    }
+    None
+}
+
+/// a short paragraph like `CHAPTER IV.`, `Book 2: The Return` or `PART THE FIRST`
+fn is_chapter(paragraph: &str) -> bool {
+    if paragraph.len() > 80 {
+        return false;
+    }
+    let mut words = paragraph
+        .split_whitespace()
+        .map(|w| w.trim_end_matches(['.', ':', ',']).to_lowercase());
+    let division = words.next().unwrap_or_default();
+    let mut number = words.next().unwrap_or_default();
+    if number == "the" {
+        number = words.next().unwrap_or_default();
+    }
+    DIVISIONS.contains(&division.as_str())
+        && !number.is_empty()
+        && (number.chars().all(|c| c.is_ascii_digit())
+            || is_roman(&number)
+            || NUMBERS.contains(&number.as_str()))
+}
+
+/// a lowercase roman numeral written the usual way, `iv` and not `iiii` or `ill`
+fn is_roman(number: &str) -> bool {
+    let value = |c: char| match c {
+        'i' => Some(1),
+        'v' => Some(5),
+        'x' => Some(10),
+        'l' => Some(50),
+        'c' => Some(100),
+        'd' => Some(500),
+        'm' => Some(1000),
+        _ => None,
+    };
+    let values: Option<Vec<usize>> = number.chars().map(value).collect();
+    let values = match values {
+        Some(values) if !values.is_empty() => values,
+        _ => return false,
+    };
+    let mut n = 0;
+    for (i, v) in values.iter().enumerate() {
+        match values.get(i + 1) {
+            Some(next) if next > v => n -= *v as isize,
+            _ => n += *v as isize,
+        }
+    }
+    n > 0 && roman(n as usize) == number
+}
+
+/// n as a lowercase roman numeral
+fn roman(mut n: usize) -> String {
+    let numerals = [
+        (1000, "m"),
+        (900, "cm"),
+        (500, "d"),
+        (400, "cd"),
+        (100, "c"),
+        (90, "xc"),
+        (50, "l"),
+        (40, "xl"),
+        (10, "x"),
+        (9, "ix"),
+        (5, "v"),
+        (4, "iv"),
+        (1, "i"),
+    ];
+    let mut numeral = String::new();
+    for (value, letters) in numerals {
+        while n >= value {
+            numeral.push_str(letters);
+            n -= value;
+        }
+    }
+    numeral
+}
+
+/// the line heading a table of contents
+fn is_contents(paragraph: &str) -> bool {
+    let heading = paragraph.trim_end_matches(['.', ':']).to_lowercase();
+    heading == "contents" || heading == "table of contents"
+}
+
+/// a short all uppercase line, a chapter's name
+fn is_title(paragraph: &str) -> bool {
+    paragraph.len() <= 80
+        && paragraph.chars().any(|c| c.is_alphabetic())
+        && !paragraph.chars().any(|c| c.is_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::index::gutenberg::{
+        extract, is_chapter, is_gutenberg, paragraphs, strip_boilerplate,
+    };
+
+    static BOOK: &str = "\u{feff}The Project Gutenberg eBook of A Short Book, by Some Author\r
+\r
+This eBook is for the use of anyone anywhere at no cost.\r
+\r
+Title: A Short Book\r
+\r
+Author: Some Author\r
+\r
+*** START OF THE PROJECT GUTENBERG EBOOK A SHORT BOOK ***\r
+\r
+CONTENTS\r
+\r
+CHAPTER I. The River\r
+CHAPTER II. The Square\r
+\r
+\r
+CHAPTER I.\r
+\r
+THE RIVER\r
+\r
+The river ran past the town, wide and\r
+slow. Nobody crossed it.\r
+\r
+CHAPTER II. The Square\r
+\r
+The Spanish square was\r
+empty.\r
+\r
+*** END OF THE PROJECT GUTENBERG EBOOK A SHORT BOOK ***\r
+\r
+Updated editions will replace the previous one.\r
+";
+
+    #[test]
+    fn strips_boilerplate() {
+        assert!(is_gutenberg(BOOK));
+        assert!(!is_gutenberg("Just some text.\n*** not a marker ***"));
+        let (header, content) = strip_boilerplate(BOOK);
+        assert!(header.contains("Title: A Short Book"));
+        assert!(content.trim_start().starts_with("CONTENTS"));
+        assert!(content.trim_end().ends_with("empty."));
+
+        let older = "header\n*** START OF THIS PROJECT GUTENBERG EBOOK X ***\nText.\n\nEnd of the Project Gutenberg EBook of X\n\n*** END OF THIS PROJECT GUTENBERG EBOOK X ***\nlicense";
+        assert_eq!(strip_boilerplate(older).1, "Text.\n\n");
+        assert_eq!(strip_boilerplate("no markers"), ("", "no markers"));
+    }
+
+    #[test]
+    fn reflows_paragraphs() {
+        assert_eq!(
+            paragraphs("one\ntwo  \n\n\n  three\r\nfour\n"),
+            vec!["one two", "three four"]
+        );
+    }
+
+    #[test]
+    fn chapters_are_headings() {
+        let docs = extract(BOOK);
+        let shown: Vec<(&str, &str)> = docs
+            .iter()
+            .map(|d| (d.headings.as_str(), d.body.as_str()))
+            .collect();
+        assert_eq!(
+            shown,
+            vec![
+                // the table of contents is left out
+                ("CHAPTER I. THE RIVER", "CHAPTER I. THE RIVER"),
+                (
+                    "CHAPTER I. THE RIVER",
+                    "The river ran past the town, wide and slow."
+                ),
+                ("CHAPTER I. THE RIVER", "Nobody crossed it."),
+                ("CHAPTER II. The Square", "CHAPTER II. The Square"),
+                ("CHAPTER II. The Square", "The Spanish square was empty."),
+            ]
+        );
+        assert!(docs
+            .iter()
+            .all(|d| d.title == "A Short Book" && d.meta == "Some Author"));
+    }
+
+    #[test]
+    fn recognizes_chapters() {
+        for heading in [
+            "CHAPTER IV.",
+            "Chapter 12",
+            "Book 2: The Return",
+            "PART THE FIRST",
+            "Stave xiv",
+            "ACT V. Scene 1",
+            "LETTER twelve",
+            "Volume MCMXIV",
+        ] {
+            assert!(is_chapter(heading), "{}", heading);
+        }
+        for text in [
+            "Act ill.",
+            "Part civil",
+            "Chapter iiii",
+            "Part the way there",
+            "Book the",
+            "Chapter",
+            "The chapter I liked",
+        ] {
+            assert!(!is_chapter(text), "{}", text);
+        }
+    }
+}
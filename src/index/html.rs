@@ -0,0 +1,361 @@
+use super::{analyze::sentences, fields::Document};
+use html_parser::{Dom, Element, Node};
+
+/// elements whose text isn't content
+static SKIPPED: [&str; 7] = [
+    "script", "style", "noscript", "template", "svg", "head", "iframe",
+];
+
+/// elements that start a new block of text
+static BLOCKS: [&str; 33] = [
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "body",
+    "br",
+    "dd",
+    "details",
+    "div",
+    "dl",
+    "dt",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "header",
+    "hr",
+    "html",
+    "li",
+    "main",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "summary",
+    "table",
+    "td",
+    "th",
+    "tr",
+    "ul",
+    "caption",
+];
+
+/// A run of text between block boundaries.
+#[derive(Debug, Default)]
+struct Block {
+    text: String,
+    anchors: Vec<String>,
+    /// alt text, which unlike link text isn't part of the block's text
+    alts: Vec<String>,
+    section: String,
+}
+
+#[derive(Debug, Default)]
+struct Walker {
+    title: String,
+    meta: Vec<String>,
+    /// (level, text) of the headings the walk is currently under
+    headings: Vec<(usize, String)>,
+    current: Block,
+    blocks: Vec<Block>,
+}
+
+/// split an html page into documents, one per sentence of its text
+///
+/// Every document gets the page's title and meta description and keywords,
+/// the headings of the section it's in and the link and alt text found in
+/// it. Headings are documents too, under their own section.
+pub fn extract(html: &str) -> Result<Vec<Document>, String> {
+    let dom = Dom::parse(html).map_err(|e| e.to_string())?;
+    let mut walker = Walker::default();
+    // the head is skipped as content, but it's where the title and meta are
+    for node in dom.children.iter() {
+        walker.head(node);
+    }
+    for node in dom.children.iter() {
+        walker.walk(node);
+    }
+    walker.flush();
+
+    let meta = walker.meta.join(" ");
+    let mut docs = Vec::new();
+    for block in walker.blocks {
+        let text = collapse(&block.text);
+        let start = docs.len();
+        for sentence in sentences(&text) {
+            let anchors: Vec<&str> = block
+                .anchors
+                .iter()
+                .filter(|a| sentence.contains(a.as_str()))
+                .map(|a| a.as_str())
+                .collect();
+            docs.push(Document {
+                title: walker.title.clone(),
+                headings: block.section.clone(),
+                body: sentence.to_string(),
+                anchors: anchors.join(" "),
+                meta: meta.clone(),
+            });
+        }
+        if let Some(first) = docs.get_mut(start) {
+            for alt in block.alts.iter() {
+                if !first.anchors.is_empty() {
+                    first.anchors.push(' ');
+                }
+                first.anchors.push_str(alt);
+            }
+        }
+    }
+    Ok(docs)
+}
+
+/// the href of every link in an html page, in page order and as written
+///
+/// Links marked `rel="nofollow"` are left out.
+pub fn links(html: &str) -> Result<Vec<String>, String> {
+    fn walk(node: &Node, links: &mut Vec<String>) {
+        if let Node::Element(e) = node {
+            let attr = |name: &str| e.attributes.get(name).cloned().flatten();
+            if e.name.eq_ignore_ascii_case("a") {
+                let nofollow = attr("rel").is_some_and(|rel| {
+                    rel.split_whitespace()
+                        .any(|r| r.eq_ignore_ascii_case("nofollow"))
+                });
+                match attr("href") {
+                    Some(href) if !nofollow => links.push(decode(href.trim())),
+                    _ => {}
+                }
+            }
+            e.children.iter().for_each(|c| walk(c, links));
+        }
+    }
+    let dom = Dom::parse(html).map_err(|e| e.to_string())?;
+    let mut links = Vec::new();
+    dom.children.iter().for_each(|c| walk(c, &mut links));
+    Ok(links)
+}
+
+impl Walker {
+    fn head(&mut self, node: &Node) {
+        if let Node::Element(e) = node {
+            match e.name.to_lowercase().as_str() {
+                "title" if self.title.is_empty() => self.title = collapse(&text_of(e)),
+                "meta" => {
+                    let attr = |name: &str| e.attributes.get(name).cloned().flatten();
+                    let name = attr("name")
+                        .or_else(|| attr("property"))
+                        .unwrap_or_default();
+                    if let ("description" | "keywords" | "og:description", Some(content)) =
+                        (name.to_lowercase().as_str(), attr("content"))
+                    {
+                        self.meta.push(collapse(&decode(&content)));
+                    }
+                }
+                "body" => {}
+                _ => e.children.iter().for_each(|c| self.head(c)),
+            }
+        }
+    }
+
+    fn walk(&mut self, node: &Node) {
+        let e = match node {
+            Node::Text(text) => {
+                push_text(&mut self.current.text, &decode(text));
+                return;
+            }
+            Node::Comment(_) => return,
+            Node::Element(e) => e,
+        };
+        let name = e.name.to_lowercase();
+        if SKIPPED.contains(&name.as_str()) || name == "title" {
+            return;
+        }
+        match name.as_str() {
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                self.flush();
+                let level = name[1..].parse().expect("h1 to h6");
+                let heading = collapse(&text_of(e));
+                self.headings.retain(|(l, _)| *l < level);
+                self.headings.push((level, heading.clone()));
+                self.current.text = heading;
+                self.flush();
+            }
+            "a" => {
+                let anchor = collapse(&text_of(e));
+                if !anchor.is_empty() {
+                    self.current.anchors.push(anchor);
+                }
+                e.children.iter().for_each(|c| self.walk(c));
+            }
+            "img" => {
+                if let Some(Some(alt)) = e.attributes.get("alt") {
+                    let alt = collapse(&decode(alt));
+                    if !alt.is_empty() {
+                        self.current.alts.push(alt);
+                    }
+                }
+            }
+            block if BLOCKS.contains(&block) => {
+                self.flush();
+                e.children.iter().for_each(|c| self.walk(c));
+                self.flush();
+            }
+            _ => e.children.iter().for_each(|c| self.walk(c)),
+        }
+    }
+
+    /// end the current block, keeping it if it has any text
+    fn flush(&mut self) {
+        let mut block = std::mem::take(&mut self.current);
+        if block.text.trim().is_empty() && block.alts.is_empty() {
+            return;
+        }
+        if block.text.trim().is_empty() {
+            // only images, their alt text is all there is to show
+            block.text = block.alts.join(". ");
+        }
+        block.section = self
+            .headings
+            .iter()
+            .map(|(_, h)| h.as_str())
+            .collect::<Vec<&str>>()
+            .join(" ");
+        self.blocks.push(block);
+    }
+}
+
+/// all text under an element, skipping what isn't content
+fn text_of(e: &Element) -> String {
+    let mut text = String::new();
+    for child in e.children.iter() {
+        match child {
+            Node::Text(t) => push_text(&mut text, &decode(t)),
+            Node::Element(c) if !SKIPPED.contains(&c.name.to_lowercase().as_str()) => {
+                push_text(&mut text, &text_of(c))
+            }
+            _ => {}
+        }
+    }
+    text
+}
+
+/// append text, spaced from what's before unless it's punctuation
+///
+/// html_parser trims text nodes, so `<a>link</a>.` can't be told from `<a>link</a> .`
+fn push_text(buf: &mut String, text: &str) {
+    let text = text.trim();
+    if text.is_empty() {
+        return;
+    }
+    let punctuation = text.starts_with(['.', ',', ';', ':', '!', '?', ')', ']']);
+    if !buf.is_empty() && !punctuation {
+        buf.push(' ');
+    }
+    buf.push_str(text);
+}
+
+/// runs of whitespace, newlines included, as single spaces
+fn collapse(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// the character references common in text, html_parser leaves them as is
+fn decode(text: &str) -> String {
+    if !text.contains('&') {
+        return text.to_string();
+    }
+    text.replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&apos;", "'")
+        .replace("&mdash;", "—")
+        .replace("&ndash;", "–")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::index::{
+        fields::Document,
+        html::{extract, links},
+    };
+
+    static PAGE: &str = r#"<!DOCTYPE html>
+<html>
+  <head>
+    <title>Rust Programming Language</title>
+    <meta name="description" content="A language empowering everyone">
+    <meta name="viewport" content="width=device-width">
+    <script>var ignored = "script text";</script>
+  </head>
+  <body>
+    <h1>Why Rust?</h1>
+    <h2>Performance</h2>
+    <p>Rust is blazingly fast. It has no <a href="/gc">garbage collector</a>.</p>
+    <h2>Reliability</h2>
+    <p>Rust&#39;s rich type system &amp; ownership model guarantee safety.</p>
+    <img src="ferris.png" alt="Ferris the crab">
+    <h1>Community</h1>
+    <ul><li>Join the <a href="/forum">forum</a></li></ul>
+  </body>
+</html>"#;
+
+    fn bodies(docs: &[Document]) -> Vec<&str> {
+        docs.iter().map(|d| d.body.as_str()).collect()
+    }
+
+    #[test]
+    fn extracts_fields() {
+        let docs = extract(PAGE).unwrap();
+        assert_eq!(
+            bodies(&docs),
+            vec![
+                "Why Rust?",
+                "Performance",
+                "Rust is blazingly fast.",
+                "It has no garbage collector.",
+                "Reliability",
+                "Rust's rich type system & ownership model guarantee safety.",
+                "Ferris the crab",
+                "Community",
+                "Join the forum",
+            ]
+        );
+        for doc in docs.iter() {
+            assert_eq!(doc.title, "Rust Programming Language");
+            assert_eq!(doc.meta, "A language empowering everyone");
+        }
+        assert_eq!(docs[2].headings, "Why Rust? Performance");
+        assert_eq!(docs[2].anchors, "");
+        assert_eq!(docs[3].anchors, "garbage collector");
+        assert_eq!(docs[5].headings, "Why Rust? Reliability");
+        assert_eq!(docs[6].anchors, "Ferris the crab");
+        assert_eq!(docs[8].headings, "Community");
+        assert_eq!(docs[8].anchors, "forum");
+    }
+
+    #[test]
+    fn finds_links() {
+        let html = r#"<html><body>
+            <a href="/gc">gc</a> <a href="/ads" rel="sponsored nofollow">ad</a>
+            <div><a href=" https://example.com/?a=1&amp;b=2 ">elsewhere</a><a name="top">top</a></div>
+        </body></html>"#;
+        assert_eq!(
+            links(html).unwrap(),
+            vec!["/gc", "https://example.com/?a=1&b=2"]
+        );
+        assert_eq!(links(PAGE).unwrap(), vec!["/gc", "/forum"]);
+    }
+
+    #[test]
+    fn plain_text_is_all_body() {
+        let docs = Document::from_text("One sentence. And another one.\n\n");
+        assert_eq!(bodies(&docs), vec!["One sentence.", "And another one."]);
+        assert!(docs.iter().all(|d| d.title.is_empty()));
+    }
+}
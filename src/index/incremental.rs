@@ -0,0 +1,478 @@
+use super::analyze::{stem, term_freqs};
+use crate::ranking::{normalization::Normalization, scorer::Scorer};
+use ndarray::{s, Array1, Array2, ArrayView2};
+use rust_stemmers::{Algorithm, Stemmer};
+use std::collections::{BTreeSet, HashMap};
+
+/// how far collection statistics may drift before every weight is recomputed
+pub static DRIFT: f32 = 0.1;
+
+/// collection statistics that term weights depend on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CollectionStats {
+    /// live documents
+    pub docs: usize,
+    /// average distinct terms per live document
+    pub avg_doc_len: f32,
+}
+
+impl CollectionStats {
+    /// largest relative change of either statistic
+    pub fn drift(&self, other: &CollectionStats) -> f32 {
+        let relative = |a: f32, b: f32| {
+            if a == 0.0 {
+                if b == 0.0 {
+                    0.0
+                } else {
+                    f32::INFINITY
+                }
+            } else {
+                (a - b).abs() / a
+            }
+        };
+        relative(self.docs as f32, other.docs as f32)
+            .max(relative(self.avg_doc_len, other.avg_doc_len))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Doc {
+    //-----------(term , freq )
+    terms: Vec<(usize, usize)>,
+    deleted: bool,
+}
+
+impl Doc {
+    fn len(&self) -> usize {
+        self.terms.len()
+    }
+}
+
+/// A document x term index that can be added to, updated and deleted from.
+///
+/// Term frequencies and postings are kept up to date on every change, but
+/// the dense weight matrix is refreshed lazily: `refresh` only weights the
+/// documents changed since the last refresh, and recomputes everything once
+/// the collection statistics have drifted too far from the ones the matrix
+/// was weighted with. Deleted documents stay as all zero rows (tombstones)
+/// so doc ids never change.
+pub struct IncrementalIndex {
+    scorer: Scorer,
+    normalization: Normalization,
+    drift: f32,
+    stemmer: Stemmer,
+    vocab: HashMap<String, usize>,
+    terms: Vec<String>,
+    //-----------------(doc  , freq )
+    postings: Vec<Vec<(usize, usize)>>,
+    docs: Vec<Doc>,
+    live: usize,
+    total_len: usize,
+    weights: Array2<f32>,
+    stale: BTreeSet<usize>,
+    weighted_with: Option<CollectionStats>,
+    /// largest raw weight at the last full reweight, for max normalization
+    scale: f32,
+}
+
+impl IncrementalIndex {
+    pub fn new(scorer: Scorer, normalization: Normalization) -> IncrementalIndex {
+        IncrementalIndex::with_drift(scorer, normalization, DRIFT)
+    }
+
+    /// drift is the relative change in collection statistics tolerated before a full reweight
+    pub fn with_drift(
+        scorer: Scorer,
+        normalization: Normalization,
+        drift: f32,
+    ) -> IncrementalIndex {
+        IncrementalIndex {
+            scorer,
+            normalization,
+            drift,
+            stemmer: Stemmer::create(Algorithm::English),
+            vocab: HashMap::new(),
+            terms: Vec::new(),
+            postings: Vec::new(),
+            docs: Vec::new(),
+            live: 0,
+            total_len: 0,
+            weights: Array2::zeros((0, 0)),
+            stale: BTreeSet::new(),
+            weighted_with: None,
+            scale: 1.0,
+        }
+    }
+
+    pub fn scorer(&self) -> Scorer {
+        self.scorer
+    }
+
+    pub fn normalization(&self) -> Normalization {
+        self.normalization
+    }
+
+    /// number of doc ids handed out, including deleted ones
+    pub fn num_docs(&self) -> usize {
+        self.docs.len()
+    }
+
+    pub fn num_terms(&self) -> usize {
+        self.terms.len()
+    }
+
+    pub fn is_deleted(&self, doc_id: usize) -> bool {
+        self.docs.get(doc_id).is_none_or(|d| d.deleted)
+    }
+
+    pub fn term_id(&self, term: &str) -> Option<usize> {
+        self.vocab.get(term).copied()
+    }
+
+    pub fn term(&self, term_id: usize) -> &str {
+        &self.terms[term_id]
+    }
+
+    /// (doc, freq) of every live document containing the term
+    pub fn postings(&self, term_id: usize) -> &[(usize, usize)] {
+        &self.postings[term_id]
+    }
+
+    pub fn stats(&self) -> CollectionStats {
+        CollectionStats {
+            docs: self.live,
+            avg_doc_len: if self.live == 0 {
+                0.0
+            } else {
+                self.total_len as f32 / self.live as f32
+            },
+        }
+    }
+
+    /// index a document, returning its doc id
+    pub fn add(&mut self, text: &str) -> usize {
+        let doc_id = self.docs.len();
+        let terms = self.intern(text);
+        self.docs.push(Doc {
+            terms: Vec::new(),
+            deleted: false,
+        });
+        self.live += 1;
+        self.set_terms(doc_id, terms);
+        doc_id
+    }
+
+    /// replace a document's text keeping its doc id, false if it's deleted or unknown
+    pub fn update(&mut self, doc_id: usize, text: &str) -> bool {
+        if self.is_deleted(doc_id) {
+            return false;
+        }
+        let terms = self.intern(text);
+        self.clear_terms(doc_id);
+        self.set_terms(doc_id, terms);
+        true
+    }
+
+    /// tombstone a document, false if it was already deleted or unknown
+    pub fn delete(&mut self, doc_id: usize) -> bool {
+        if self.is_deleted(doc_id) {
+            return false;
+        }
+        self.clear_terms(doc_id);
+        self.docs[doc_id].deleted = true;
+        self.live -= 1;
+        self.stale.remove(&doc_id);
+        // zeroed right away so a deleted doc can never match, even before a refresh
+        if doc_id < self.weights.nrows() {
+            self.weights.row_mut(doc_id).fill(0.0);
+        }
+        true
+    }
+
+    /// whether the weight matrix reflects every change so far
+    pub fn is_fresh(&self) -> bool {
+        self.stale.is_empty() && self.weights.dim() == (self.docs.len(), self.terms.len())
+    }
+
+    /// bring the weight matrix up to date, returning true if it needed a full reweight
+    pub fn refresh(&mut self) -> bool {
+        let stats = self.stats();
+        let drifted = match self.weighted_with {
+            Some(weighted_with) => weighted_with.drift(&stats) > self.drift,
+            None => true,
+        };
+        if drifted {
+            self.reweight();
+            return true;
+        }
+
+        self.grow();
+        let stale = std::mem::take(&mut self.stale);
+        for doc_id in stale.iter() {
+            let raw = self.raw_weights(*doc_id, &stats);
+            let row_max = raw.iter().fold(0.0_f32, |m, (_, w)| m.max(*w));
+            if self.normalization == Normalization::Max && row_max > self.scale {
+                // every other row would have to be scaled down too
+                self.reweight();
+                return true;
+            }
+            self.write_row(*doc_id, raw);
+        }
+        false
+    }
+
+    /// recompute every weight from the current collection statistics
+    pub fn reweight(&mut self) {
+        self.grow();
+        let stats = self.stats();
+        let raw: Vec<Vec<(usize, f32)>> = (0..self.docs.len())
+            .map(|doc_id| self.raw_weights(doc_id, &stats))
+            .collect();
+        self.scale = raw
+            .iter()
+            .flatten()
+            .fold(0.0_f32, |m, (_, w)| m.max(*w))
+            .max(f32::MIN_POSITIVE);
+        self.weights.fill(0.0);
+        for (doc_id, row) in raw.into_iter().enumerate() {
+            self.write_row(doc_id, row);
+        }
+        self.stale.clear();
+        self.weighted_with = Some(stats);
+    }
+
+    /// document x term weights, call `refresh` first for them to reflect recent changes
+    pub fn weights(&self) -> ArrayView2<'_, f32> {
+        self.weights.view()
+    }
+
+    /// embed query terms into the term space, unknown terms are ignored
+    pub fn embed<'a, I: IntoIterator<Item = &'a str>>(&self, terms: I) -> Array1<f32> {
+        let mut query: Array1<f32> = Array1::zeros(self.terms.len());
+        for term in terms {
+            if let Some(tidx) = self.term_id(term) {
+                query[tidx] = 1.0;
+            }
+        }
+        query
+    }
+
+    /// stem a word the same way documents are
+    pub fn stem(&self, word: &str) -> String {
+        stem(word, &self.stemmer)
+    }
+
+    /// term ids and frequencies for text, adding unseen terms to the vocabulary
+    fn intern(&mut self, text: &str) -> Vec<(usize, usize)> {
+        term_freqs(text, &self.stemmer)
+            .into_iter()
+            .map(|(term, freq)| {
+                let tidx = match self.vocab.get(&term) {
+                    Some(tidx) => *tidx,
+                    None => {
+                        let tidx = self.terms.len();
+                        self.vocab.insert(term.clone(), tidx);
+                        self.terms.push(term);
+                        self.postings.push(Vec::new());
+                        tidx
+                    }
+                };
+                (tidx, freq)
+            })
+            .collect()
+    }
+
+    fn set_terms(&mut self, doc_id: usize, terms: Vec<(usize, usize)>) {
+        for (tidx, freq) in terms.iter() {
+            self.postings[*tidx].push((doc_id, *freq));
+        }
+        self.total_len += terms.len();
+        self.docs[doc_id].terms = terms;
+        self.stale.insert(doc_id);
+    }
+
+    fn clear_terms(&mut self, doc_id: usize) {
+        let terms = std::mem::take(&mut self.docs[doc_id].terms);
+        for (tidx, _) in terms.iter() {
+            self.postings[*tidx].retain(|(d, _)| *d != doc_id);
+        }
+        self.total_len -= terms.len();
+    }
+
+    /// make room for documents and terms added since the matrix was last sized
+    fn grow(&mut self) {
+        let (rows, cols) = self.weights.dim();
+        if (rows, cols) == (self.docs.len(), self.terms.len()) {
+            return;
+        }
+        let mut weights = Array2::zeros((self.docs.len(), self.terms.len()));
+        weights.slice_mut(s![..rows, ..cols]).assign(&self.weights);
+        self.weights = weights;
+    }
+
+    fn raw_weights(&self, doc_id: usize, stats: &CollectionStats) -> Vec<(usize, f32)> {
+        let doc = &self.docs[doc_id];
+        if doc.deleted {
+            return Vec::new();
+        }
+        doc.terms
+            .iter()
+            .map(|(tidx, freq)| {
+                let weight = self.scorer.weight(
+                    *freq,
+                    doc.len(),
+                    self.postings[*tidx].len(),
+                    stats.docs,
+                    stats.avg_doc_len,
+                );
+                (*tidx, weight)
+            })
+            .collect()
+    }
+
+    fn write_row(&mut self, doc_id: usize, raw: Vec<(usize, f32)>) {
+        let divisor = match self.normalization {
+            Normalization::None => 1.0,
+            Normalization::Max => self.scale,
+            Normalization::L2 => raw.iter().map(|(_, w)| w * w).sum::<f32>().sqrt(),
+        };
+        let mut row = self.weights.row_mut(doc_id);
+        row.fill(0.0);
+        if divisor > 0.0 {
+            for (tidx, w) in raw {
+                row[tidx] = w / divisor;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::s;
+
+    use crate::{
+        index::incremental::IncrementalIndex,
+        ranking::{normalization::Normalization, scorer::Scorer},
+    };
+
+    static SENTS: [&str; 5] = [
+        "The rust project receives support from companies.",
+        "Rust is fast and memory efficient.",
+        "Companies use rust in production.",
+        "The weather is nice today.",
+        "Support the project by donating.",
+    ];
+
+    fn built(scorer: Scorer, normalization: Normalization, sents: &[&str]) -> IncrementalIndex {
+        let mut index = IncrementalIndex::new(scorer, normalization);
+        for sent in sents {
+            index.add(sent);
+        }
+        index.refresh();
+        index
+    }
+
+    #[test]
+    fn add_and_embed() {
+        let index = built(Scorer::BM25, Normalization::None, &SENTS);
+        assert_eq!(index.num_docs(), 5);
+        assert_eq!(index.stats().docs, 5);
+        assert!(index.is_fresh());
+        let rust = index.term_id("rust").unwrap();
+        let docs: Vec<usize> = index.postings(rust).iter().map(|(d, _)| *d).collect();
+        assert_eq!(docs, vec![0, 1, 2]);
+        let query = index.embed(["rust", "compani", "unknown"]);
+        assert_eq!(query.sum(), 2.0);
+        assert!(index.weights()[[1, rust]] > 0.0);
+        assert_eq!(index.weights()[[3, rust]], 0.0);
+    }
+
+    #[test]
+    fn delete_tombstones() {
+        let mut index = built(Scorer::TFIDF, Normalization::None, &SENTS);
+        let rust = index.term_id("rust").unwrap();
+        assert!(index.delete(1));
+        assert!(!index.delete(1));
+        assert!(index.is_deleted(1));
+        assert!(!index.update(1, "rust again"));
+        // zeroed before any refresh
+        assert!(index.weights().row(1).iter().all(|w| *w == 0.0));
+        assert_eq!(index.postings(rust).len(), 2);
+        assert_eq!(index.stats().docs, 4);
+        assert_eq!(index.num_docs(), 5);
+    }
+
+    #[test]
+    fn update_keeps_doc_id() {
+        let mut index = built(Scorer::TFIDF, Normalization::None, &SENTS);
+        let weather = index.term_id("weather").unwrap();
+        assert!(index.update(3, "Rust companies support the weather."));
+        assert!(!index.is_fresh());
+        index.refresh();
+        assert!(index.weights()[[3, weather]] > 0.0);
+        let rust = index.term_id("rust").unwrap();
+        assert!(index.postings(rust).iter().any(|(d, _)| *d == 3));
+    }
+
+    #[test]
+    fn reweight_matches_fresh_build() {
+        for scorer in [Scorer::TFIDF, Scorer::BM25] {
+            for normalization in [Normalization::None, Normalization::Max, Normalization::L2] {
+                let mut index = built(scorer, normalization, &SENTS[..3]);
+                index.add(SENTS[3]);
+                index.add(SENTS[4]);
+                index.delete(0);
+                index.reweight();
+                // doc 0 is a tombstone, the rest should be as if built without it
+                let fresh = built(scorer, normalization, &SENTS[1..]);
+                let (rows, cols) = fresh.weights().dim();
+                assert_eq!(index.num_docs(), rows + 1);
+                for doc in 0..rows {
+                    for term in 0..cols {
+                        let t = fresh.term(term);
+                        let expected = fresh.weights()[[doc, term]];
+                        let got = index.weights()[[doc + 1, index.term_id(t).unwrap()]];
+                        assert!(
+                            (expected - got).abs() < 1e-6,
+                            "{:?} {:?} doc {} {}",
+                            scorer,
+                            normalization,
+                            doc,
+                            t
+                        );
+                    }
+                }
+                assert!(index.weights().row(0).iter().all(|w| *w == 0.0));
+            }
+        }
+    }
+
+    #[test]
+    fn refresh_is_lazy_until_drift() {
+        let mut sents: Vec<String> = Vec::new();
+        for i in 0..40 {
+            sents.push(format!("rust document number {} about support", i));
+        }
+        let refs: Vec<&str> = sents.iter().map(|s| s.as_str()).collect();
+        let mut index = IncrementalIndex::with_drift(Scorer::TFIDF, Normalization::None, 0.1);
+        for sent in refs.iter() {
+            index.add(sent);
+        }
+        assert!(index.refresh());
+        let before = index.weights().slice(s![..40, ..]).to_owned();
+
+        // one more doc is well within 10%, old rows keep their weights
+        index.add("rust support elsewhere");
+        assert!(!index.refresh());
+        assert!(index.is_fresh());
+        assert_eq!(index.weights().slice(s![..40, ..before.ncols()]), before);
+        assert!(index.weights().row(40).sum() > 0.0);
+
+        // doubling the collection is not
+        for sent in refs.iter() {
+            index.add(sent);
+        }
+        assert!(index.refresh());
+        assert_ne!(index.weights().slice(s![..40, ..before.ncols()]), before);
+    }
+}
@@ -0,0 +1,120 @@
+/// What a merge policy needs to know about a segment
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SegmentInfo {
+    pub id: u64,
+    /// documents in the segment, including deleted ones
+    pub docs: usize,
+    /// documents deleted since the segment was written
+    pub deleted: usize,
+}
+
+impl SegmentInfo {
+    pub fn deleted_ratio(&self) -> f32 {
+        if self.docs == 0 {
+            0.0
+        } else {
+            self.deleted as f32 / self.docs as f32
+        }
+    }
+}
+
+/// Tiered merge policy.
+///
+/// Segments are grouped into tiers by size, each tier `merge_factor` times
+/// larger than the last. Once a tier holds `merge_factor` segments they're
+/// merged into one of the next tier, so the number of segments stays
+/// logarithmic in the number of documents. A segment that has had more than
+/// `max_deleted_ratio` of its documents deleted is rewritten on its own to
+/// purge them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MergePolicy {
+    pub merge_factor: usize,
+    pub max_deleted_ratio: f32,
+}
+
+impl Default for MergePolicy {
+    fn default() -> Self {
+        MergePolicy {
+            merge_factor: 4,
+            max_deleted_ratio: 0.2,
+        }
+    }
+}
+
+impl MergePolicy {
+    /// ids of the segments to merge next, if any should be
+    pub fn select(&self, segments: &[SegmentInfo]) -> Option<Vec<u64>> {
+        let factor = self.merge_factor.max(2);
+        let mut by_size: Vec<&SegmentInfo> = segments.iter().collect();
+        by_size.sort_by_key(|s| (s.docs - s.deleted, s.id));
+
+        let tier = |s: &SegmentInfo| {
+            let mut live = (s.docs - s.deleted).max(1);
+            let mut tier = 0;
+            while live >= factor {
+                live /= factor;
+                tier += 1;
+            }
+            tier
+        };
+        // smallest tiers first, they're cheapest to merge
+        let mut start = 0;
+        while start < by_size.len() {
+            let t = tier(by_size[start]);
+            let end = start + by_size[start..].iter().take_while(|s| tier(s) == t).count();
+            if end - start >= factor {
+                return Some(
+                    by_size[start..start + factor]
+                        .iter()
+                        .map(|s| s.id)
+                        .collect(),
+                );
+            }
+            start = end;
+        }
+
+        segments
+            .iter()
+            .filter(|s| s.deleted > 0 && s.deleted_ratio() > self.max_deleted_ratio)
+            .max_by(|a, b| a.deleted_ratio().total_cmp(&b.deleted_ratio()))
+            .map(|s| vec![s.id])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::index::merge::{MergePolicy, SegmentInfo};
+
+    fn info(id: u64, docs: usize, deleted: usize) -> SegmentInfo {
+        SegmentInfo { id, docs, deleted }
+    }
+
+    #[test]
+    fn merges_full_tier() {
+        let policy = MergePolicy::default();
+        let segments = [
+            info(0, 100, 0),
+            info(1, 10, 0),
+            info(2, 12, 0),
+            info(3, 9, 0),
+        ];
+        assert_eq!(policy.select(&segments), None);
+        let segments = [
+            info(0, 100, 0),
+            info(1, 10, 0),
+            info(2, 12, 0),
+            info(3, 9, 0),
+            info(4, 11, 0),
+            info(5, 8, 0),
+        ];
+        // the four smallest of the tier
+        assert_eq!(policy.select(&segments), Some(vec![5, 3, 1, 4]));
+    }
+
+    #[test]
+    fn purges_deletes() {
+        let policy = MergePolicy::default();
+        let segments = [info(0, 100, 10), info(1, 100, 50), info(2, 3, 0)];
+        assert_eq!(policy.select(&segments), Some(vec![1]));
+    }
+}
@@ -0,0 +1,429 @@
+use super::{analyze::stem, incremental::IncrementalIndex};
+use crate::ranking::{normalization::Normalization, scorer::Scorer};
+use memmap2::Mmap;
+use ndarray::{Array1, ArrayView2, ArrayViewD};
+use ndarray_npy::{write_npy, ViewElement, ViewNpyExt, WriteNpyExt};
+use rust_stemmers::{Algorithm, Stemmer};
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io,
+    marker::PhantomData,
+    path::Path,
+};
+
+/// document x term weights, row major f32
+static WEIGHTS: &str = "weights.npy";
+/// where each term's postings start in the postings arrays, one past the last term at the end
+static POSTING_OFFSETS: &str = "posting_offsets.npy";
+/// doc ids of every postings list back to back, ascending within a list
+static POSTING_DOCS: &str = "posting_docs.npy";
+/// term frequencies matching `POSTING_DOCS`
+static POSTING_FREQS: &str = "posting_freqs.npy";
+/// utf-8 text of every document back to back
+static TEXTS: &str = "texts.npy";
+/// where each document's text starts, one past the last document at the end
+static TEXT_OFFSETS: &str = "text_offsets.npy";
+/// one term per line in term id order
+static TERMS: &str = "terms.txt";
+/// scorer and normalization the weights were computed with
+static META: &str = "meta.txt";
+
+/// A saved index, memory-mapped rather than read.
+///
+/// Everything but the vocabulary is stored as `.npy` arrays whose data is
+/// viewed in place, so opening only maps the files, parses their headers and
+/// checks the offsets, and pages are read in as scoring touches them.
+pub struct MmapIndex {
+    scorer: Scorer,
+    normalization: Normalization,
+    stemmer: Stemmer,
+    vocab: HashMap<String, usize>,
+    terms: Vec<String>,
+    weights: Mapped<f32>,
+    posting_offsets: Mapped<u64>,
+    posting_docs: Mapped<u64>,
+    posting_freqs: Mapped<u64>,
+    texts: Mapped<u8>,
+    text_offsets: Mapped<u64>,
+}
+
+/// An `.npy` array mapped in place, its header parsed once on open.
+struct Mapped<T> {
+    mmap: Mmap,
+    /// where the data starts in the file
+    start: usize,
+    len: usize,
+    shape: Vec<usize>,
+    element: PhantomData<T>,
+}
+
+impl MmapIndex {
+    /// write a refreshed index and the text of its documents into `dir`
+    pub fn save<P: AsRef<Path>>(
+        dir: P,
+        index: &IncrementalIndex,
+        texts: &[&str],
+    ) -> io::Result<()> {
+        if !index.is_fresh() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "refresh the index before saving it",
+            ));
+        }
+        if index.num_docs() != texts.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} texts for {} documents", texts.len(), index.num_docs()),
+            ));
+        }
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+
+        write(dir.join(WEIGHTS), &index.weights())?;
+
+        let mut offsets = Vec::with_capacity(index.num_terms() + 1);
+        let mut docs = Vec::new();
+        let mut freqs = Vec::new();
+        for tidx in 0..index.num_terms() {
+            offsets.push(docs.len() as u64);
+            // updates append to postings, so they aren't in doc id order
+            let mut postings = index.postings(tidx).to_vec();
+            postings.sort_unstable();
+            for (doc_id, freq) in postings {
+                docs.push(doc_id as u64);
+                freqs.push(freq as u64);
+            }
+        }
+        offsets.push(docs.len() as u64);
+        write(dir.join(POSTING_OFFSETS), &Array1::from(offsets))?;
+        write(dir.join(POSTING_DOCS), &Array1::from(docs))?;
+        write(dir.join(POSTING_FREQS), &Array1::from(freqs))?;
+
+        let mut offsets = Vec::with_capacity(texts.len() + 1);
+        let mut bytes = Vec::new();
+        for text in texts {
+            offsets.push(bytes.len() as u64);
+            bytes.extend_from_slice(text.as_bytes());
+        }
+        offsets.push(bytes.len() as u64);
+        write(dir.join(TEXT_OFFSETS), &Array1::from(offsets))?;
+        write(dir.join(TEXTS), &Array1::from(bytes))?;
+
+        let terms: Vec<&str> = (0..index.num_terms()).map(|t| index.term(t)).collect();
+        fs::write(dir.join(TERMS), terms.join("\n"))?;
+        fs::write(
+            dir.join(META),
+            format!(
+                "scorer={}\nnorm={}\n",
+                format!("{:?}", index.scorer()).to_lowercase(),
+                format!("{:?}", index.normalization()).to_lowercase()
+            ),
+        )?;
+        Ok(())
+    }
+
+    /// map an index saved with `save`, checking every array is readable
+    ///
+    /// Only the arrays' headers, sizes and offsets are checked, the texts
+    /// aren't read until they're asked for.
+    pub fn open<P: AsRef<Path>>(dir: P) -> io::Result<MmapIndex> {
+        let dir = dir.as_ref();
+        let mut scorer = None;
+        let mut normalization = None;
+        for line in fs::read_to_string(dir.join(META))?.lines() {
+            match line.split_once('=') {
+                Some(("scorer", value)) => scorer = value.parse().ok(),
+                Some(("norm", value)) => normalization = value.parse().ok(),
+                _ => {}
+            }
+        }
+        let terms: Vec<String> = fs::read_to_string(dir.join(TERMS))?
+            .lines()
+            .map(|t| t.to_string())
+            .collect();
+        let vocab = terms
+            .iter()
+            .enumerate()
+            .map(|(tidx, term)| (term.clone(), tidx))
+            .collect();
+
+        let index = MmapIndex {
+            scorer: scorer.ok_or_else(|| invalid("missing scorer"))?,
+            normalization: normalization.ok_or_else(|| invalid("missing normalization"))?,
+            stemmer: Stemmer::create(Algorithm::English),
+            vocab,
+            terms,
+            weights: Mapped::open(dir.join(WEIGHTS), 2)?,
+            posting_offsets: Mapped::open(dir.join(POSTING_OFFSETS), 1)?,
+            posting_docs: Mapped::open(dir.join(POSTING_DOCS), 1)?,
+            posting_freqs: Mapped::open(dir.join(POSTING_FREQS), 1)?,
+            texts: Mapped::open(dir.join(TEXTS), 1)?,
+            text_offsets: Mapped::open(dir.join(TEXT_OFFSETS), 1)?,
+        };
+
+        // so the accessors below can't fail
+        let offsets = index.posting_offsets.as_slice();
+        let text_offsets = index.text_offsets.as_slice();
+        if index.weights.shape[1] != index.terms.len()
+            || offsets.len() != index.terms.len() + 1
+            || index.posting_docs.len != index.posting_freqs.len
+            || text_offsets.len() != index.weights.shape[0] + 1
+        {
+            return Err(invalid("index files don't agree on their sizes"));
+        }
+        if !bounds(offsets, index.posting_docs.len) || !bounds(text_offsets, index.texts.len) {
+            return Err(invalid(
+                "offsets must ascend from 0 to the end of their array",
+            ));
+        }
+        Ok(index)
+    }
+
+    pub fn scorer(&self) -> Scorer {
+        self.scorer
+    }
+
+    pub fn normalization(&self) -> Normalization {
+        self.normalization
+    }
+
+    pub fn num_docs(&self) -> usize {
+        self.weights.shape[0]
+    }
+
+    pub fn num_terms(&self) -> usize {
+        self.terms.len()
+    }
+
+    pub fn term_id(&self, term: &str) -> Option<usize> {
+        self.vocab.get(term).copied()
+    }
+
+    pub fn term(&self, term_id: usize) -> &str {
+        &self.terms[term_id]
+    }
+
+    /// document x term weights, viewed in place
+    pub fn weights(&self) -> ArrayView2<'_, f32> {
+        let shape = (self.weights.shape[0], self.weights.shape[1]);
+        ArrayView2::from_shape(shape, self.weights.as_slice()).expect("checked on open")
+    }
+
+    /// doc ids and frequencies of every document containing the term, ascending by doc id
+    pub fn postings(&self, term_id: usize) -> (&[u64], &[u64]) {
+        let offsets = self.posting_offsets.as_slice();
+        let range = offsets[term_id] as usize..offsets[term_id + 1] as usize;
+        (
+            &self.posting_docs.as_slice()[range.clone()],
+            &self.posting_freqs.as_slice()[range],
+        )
+    }
+
+    /// text the document was indexed from
+    ///
+    /// `save` only writes utf-8, so a text that isn't was corrupted on disk
+    /// and is cut short at its first invalid byte.
+    pub fn text(&self, doc_id: usize) -> &str {
+        let bytes = self.text_bytes(doc_id);
+        match std::str::from_utf8(bytes) {
+            Ok(text) => text,
+            Err(e) => std::str::from_utf8(&bytes[..e.valid_up_to()]).expect("valid up to here"),
+        }
+    }
+
+    /// embed query terms into the term space, unknown terms are ignored
+    pub fn embed<'a, I: IntoIterator<Item = &'a str>>(&self, terms: I) -> Array1<f32> {
+        let mut query: Array1<f32> = Array1::zeros(self.terms.len());
+        for term in terms {
+            if let Some(tidx) = self.term_id(term) {
+                query[tidx] = 1.0;
+            }
+        }
+        query
+    }
+
+    /// stem a word the same way documents are
+    pub fn stem(&self, word: &str) -> String {
+        stem(word, &self.stemmer)
+    }
+
+    fn text_bytes(&self, doc_id: usize) -> &[u8] {
+        let offsets = self.text_offsets.as_slice();
+        &self.texts.as_slice()[offsets[doc_id] as usize..offsets[doc_id + 1] as usize]
+    }
+}
+
+impl<T: ViewElement> Mapped<T> {
+    /// map an array with `ndim` dimensions, finding where its data is
+    fn open<P: AsRef<Path>>(path: P, ndim: usize) -> io::Result<Mapped<T>> {
+        let mmap = map(path)?;
+        let view = ArrayViewD::<T>::view_npy(&mmap).map_err(invalid)?;
+        if view.ndim() != ndim {
+            return Err(invalid(format!("expected {} dimensions", ndim)));
+        }
+        let data = view
+            .as_slice()
+            .ok_or_else(|| invalid("arrays must be row major"))?;
+        let start = match data.is_empty() {
+            true => 0,
+            false => data.as_ptr() as usize - mmap.as_ptr() as usize,
+        };
+        let (len, shape) = (data.len(), view.shape().to_vec());
+        Ok(Mapped {
+            mmap,
+            start,
+            len,
+            shape,
+            element: PhantomData,
+        })
+    }
+
+    fn as_slice(&self) -> &[T] {
+        if self.len == 0 {
+            return &[];
+        }
+        // view_npy found the data on open, aligned for T and inside the map,
+        // and `map` is never changed in place
+        unsafe { std::slice::from_raw_parts(self.mmap.as_ptr().add(self.start).cast(), self.len) }
+    }
+}
+
+/// whether offsets start at 0 and ascend to the end of the array they index
+fn bounds(offsets: &[u64], len: usize) -> bool {
+    offsets.first() == Some(&0)
+        && offsets.windows(2).all(|w| w[0] <= w[1])
+        && offsets.last().map(|o| *o as usize) == Some(len)
+}
+
+fn write<P: AsRef<Path>, T: WriteNpyExt>(path: P, array: &T) -> io::Result<()> {
+    write_npy(path, array).map_err(invalid)
+}
+
+fn map<P: AsRef<Path>>(path: P) -> io::Result<Mmap> {
+    let file = File::open(path)?;
+    // the index files are only ever replaced by `save`, never changed in place
+    unsafe { Mmap::map(&file) }
+}
+
+fn invalid<E: ToString>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::Array1;
+    use ndarray_npy::{read_npy, write_npy};
+
+    use crate::{
+        index::{incremental::IncrementalIndex, mmap::MmapIndex},
+        ranking::{
+            normalization::Normalization, op::Op, rank::rank, retriever::Model, scorer::Scorer,
+        },
+    };
+
+    static SENTS: [&str; 5] = [
+        "The rust project receives support from companies.",
+        "Rust is fast and memory efficient.",
+        "Companies use rust in production.",
+        "The weather is nice today.",
+        "Support the project by donating, ünïcödé welcome.",
+    ];
+
+    #[test]
+    fn round_trips() {
+        let mut index = IncrementalIndex::new(Scorer::TFIDF, Normalization::Max);
+        for sent in SENTS.iter() {
+            index.add(sent);
+        }
+        index.update(0, SENTS[0]);
+        index.refresh();
+        let dir = tempfile::tempdir().unwrap();
+        MmapIndex::save(dir.path(), &index, &SENTS).unwrap();
+
+        let mapped = MmapIndex::open(dir.path()).unwrap();
+        assert_eq!(mapped.scorer(), Scorer::TFIDF);
+        assert_eq!(mapped.normalization(), Normalization::Max);
+        assert_eq!(mapped.num_docs(), 5);
+        assert_eq!(mapped.num_terms(), index.num_terms());
+        assert_eq!(mapped.weights(), index.weights());
+        assert_eq!(mapped.text(4), SENTS[4]);
+
+        let rust = mapped.term_id("rust").unwrap();
+        let (docs, freqs) = mapped.postings(rust);
+        // doc 0 was updated, so it's last in the in memory postings
+        assert_eq!(docs, &[0, 1, 2]);
+        assert_eq!(freqs, &[1, 1, 1]);
+
+        let terms = ["rust", "compani"];
+        let (a, _) = rank(&index.embed(terms).view(), &index.weights(), &Op::OR, 2.0);
+        let (b, _) = rank(&mapped.embed(terms).view(), &mapped.weights(), &Op::OR, 2.0);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn models_check_the_saved_weights() {
+        let dir = tempfile::tempdir().unwrap();
+        for (scorer, ok) in [(Scorer::TFIDF, false), (Scorer::BM25, true)] {
+            let mut index = IncrementalIndex::new(scorer, Normalization::None);
+            for sent in SENTS.iter() {
+                index.add(sent);
+            }
+            index.refresh();
+            MmapIndex::save(dir.path(), &index, &SENTS).unwrap();
+            let mapped = MmapIndex::open(dir.path()).unwrap();
+            let checked = Model::BM25.check(mapped.scorer(), mapped.normalization());
+            assert_eq!(checked.is_ok(), ok, "{:?}", scorer);
+        }
+    }
+
+    #[test]
+    fn rejects_mismatched_files() {
+        let mut index = IncrementalIndex::new(Scorer::BM25, Normalization::None);
+        for sent in SENTS.iter() {
+            index.add(sent);
+        }
+        index.refresh();
+        let dir = tempfile::tempdir().unwrap();
+        MmapIndex::save(dir.path(), &index, &SENTS).unwrap();
+        std::fs::write(dir.path().join("terms.txt"), "rust").unwrap();
+        assert!(MmapIndex::open(dir.path()).is_err());
+    }
+
+    #[test]
+    fn rejects_offsets_out_of_order() {
+        let mut index = IncrementalIndex::new(Scorer::BM25, Normalization::None);
+        for sent in SENTS.iter() {
+            index.add(sent);
+        }
+        index.refresh();
+        let dir = tempfile::tempdir().unwrap();
+        MmapIndex::save(dir.path(), &index, &SENTS).unwrap();
+        let path = dir.path().join("text_offsets.npy");
+        let mut offsets: Array1<u64> = read_npy(&path).unwrap();
+        offsets.swap(1, 2);
+        write_npy(&path, &offsets).unwrap();
+        assert!(MmapIndex::open(dir.path()).is_err());
+
+        MmapIndex::save(dir.path(), &index, &SENTS).unwrap();
+        let path = dir.path().join("posting_offsets.npy");
+        let mut offsets: Array1<u64> = read_npy(&path).unwrap();
+        offsets[0] = 1;
+        write_npy(&path, &offsets).unwrap();
+        assert!(MmapIndex::open(dir.path()).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_input_on_save() {
+        let mut index = IncrementalIndex::new(Scorer::BM25, Normalization::None);
+        for sent in SENTS.iter() {
+            index.add(sent);
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let stale = MmapIndex::save(dir.path(), &index, &SENTS).unwrap_err();
+        assert_eq!(stale.kind(), std::io::ErrorKind::InvalidInput);
+        index.refresh();
+        let short = MmapIndex::save(dir.path(), &index, &SENTS[..2]).unwrap_err();
+        assert_eq!(short.kind(), std::io::ErrorKind::InvalidInput);
+    }
+}
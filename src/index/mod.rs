@@ -0,0 +1,13 @@
+pub mod analyze;
+pub mod context;
+pub mod dedup;
+pub mod fields;
+pub mod gutenberg;
+pub mod html;
+pub mod incremental;
+pub mod merge;
+pub mod mmap;
+pub mod query;
+pub mod segment;
+pub mod segmented;
+pub mod synonyms;
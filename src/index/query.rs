@@ -0,0 +1,516 @@
+use super::fields::{Boosts, Field, FieldedIndex};
+use crate::ranking::{
+    op::Op,
+    rank::{finish, K},
+    rank_result::RankResult,
+    stats::SearchStats,
+    top_k::TopK,
+};
+use ndarray::parallel::prelude::*;
+use ndarray::{ArrayView1, ArrayView2, Axis};
+use std::{fmt, iter::Peekable, str::Chars, time::Instant};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// A boolean query over fields, like `title:rust AND body:(company OR support)`.
+///
+/// `AND` binds tighter than `OR`, terms next to each other without either
+/// are joined by the default op, and a field applies to everything in the
+/// parentheses after it. Terms without a field match any field. Terms are
+/// split into words the way documents are, so `company-backed` is the two
+/// terms `company` and `backed` joined by the default op, and a colon after
+/// anything but a field name is punctuation, so `note: rust` is two terms.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Query {
+    Term(Option<Field>, String),
+    And(Vec<Query>),
+    Or(Vec<Query>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Field(Field),
+    Open,
+    Close,
+    And,
+    Or,
+}
+
+impl Query {
+    /// parse a query, joining terms without an op between them by `default`
+    pub fn parse(input: &str, default: Op) -> Result<Query, String> {
+        let mut parser = Parser {
+            tokens: tokenize(input).into_iter().peekable(),
+            default,
+        };
+        let query = parser.or(None)?;
+        match parser.tokens.next() {
+            None => Ok(query),
+            Some(token) => Err(format!("unexpected {:?}", token)),
+        }
+    }
+
+    /// whether this is just unscoped terms joined by op, all an embedded query can express
+    pub fn is_flat(&self, op: Op) -> bool {
+        let terms = |qs: &[Query]| qs.iter().all(|q| matches!(q, Query::Term(None, _)));
+        match self {
+            Query::Term(field, _) => field.is_none(),
+            Query::And(qs) => op == Op::AND && terms(qs),
+            Query::Or(qs) => op == Op::OR && terms(qs),
+        }
+    }
+
+    /// every term in the query, left to right, repeats included
+    pub fn terms(&self) -> Vec<&str> {
+        match self {
+            Query::Term(_, term) => vec![term.as_str()],
+            Query::And(qs) | Query::Or(qs) => qs.iter().flat_map(|q| q.terms()).collect(),
+        }
+    }
+
+    /// stem every term the way the index does
+    pub fn stemmed(&self, index: &FieldedIndex) -> Query {
+        match self {
+            Query::Term(field, term) => Query::Term(*field, index.stem(term)),
+            Query::And(qs) => Query::And(qs.iter().map(|q| q.stemmed(index)).collect()),
+            Query::Or(qs) => Query::Or(qs.iter().map(|q| q.stemmed(index)).collect()),
+        }
+    }
+}
+
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let join = |f: &mut fmt::Formatter, qs: &[Query], op: &str| {
+            write!(f, "(")?;
+            for (i, q) in qs.iter().enumerate() {
+                if i > 0 {
+                    write!(f, " {} ", op)?;
+                }
+                write!(f, "{}", q)?;
+            }
+            write!(f, ")")
+        };
+        match self {
+            Query::Term(Some(field), term) => write!(f, "{}:{}", field, term),
+            Query::Term(None, term) => write!(f, "{}", term),
+            Query::And(qs) => join(f, qs, "AND"),
+            Query::Or(qs) => join(f, qs, "OR"),
+        }
+    }
+}
+
+fn tokenize(input: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars: Peekable<Chars> = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            c if c.is_whitespace() => {}
+            c => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.peek() {
+                    if c.is_whitespace() || *c == '(' || *c == ')' || *c == ':' {
+                        break;
+                    }
+                    word.push(*c);
+                    chars.next();
+                }
+                // a colon after anything but a field name is just punctuation
+                let field = match chars.peek() {
+                    Some(':') => {
+                        chars.next();
+                        word.parse().ok()
+                    }
+                    _ => None,
+                };
+                match (field, word.as_str()) {
+                    (Some(field), _) => tokens.push(Token::Field(field)),
+                    (None, "AND") => tokens.push(Token::And),
+                    (None, "OR") => tokens.push(Token::Or),
+                    (None, _) => words(&word, &mut tokens),
+                }
+            }
+        }
+    }
+    tokens
+}
+
+/// the word's unicode words, nothing when it's only punctuation
+///
+/// Several words after a field are parenthesized so it scopes them all,
+/// elsewhere they're left as operands alongside their neighbours.
+fn words(word: &str, tokens: &mut Vec<Token>) {
+    let words: Vec<&str> = word.unicode_words().collect();
+    let group = words.len() > 1 && matches!(tokens.last(), Some(Token::Field(_)));
+    if group {
+        tokens.push(Token::Open);
+    }
+    tokens.extend(words.iter().map(|w| Token::Word(w.to_string())));
+    if group {
+        tokens.push(Token::Close);
+    }
+}
+
+struct Parser<I: Iterator<Item = Token>> {
+    tokens: Peekable<I>,
+    default: Op,
+}
+
+impl<I: Iterator<Item = Token>> Parser<I> {
+    /// whether the next token starts an operand, so an op was left out before it
+    fn implicit(&mut self, op: Op) -> bool {
+        self.default == op
+            && matches!(
+                self.tokens.peek(),
+                Some(Token::Word(_)) | Some(Token::Field(_)) | Some(Token::Open)
+            )
+    }
+
+    fn or(&mut self, field: Option<Field>) -> Result<Query, String> {
+        let mut operands = vec![self.and(field)?];
+        loop {
+            if self.tokens.peek() == Some(&Token::Or) {
+                self.tokens.next();
+            } else if !self.implicit(Op::OR) {
+                break;
+            }
+            operands.push(self.and(field)?);
+        }
+        Ok(join(operands, Query::Or))
+    }
+
+    fn and(&mut self, field: Option<Field>) -> Result<Query, String> {
+        let mut operands = vec![self.operand(field)?];
+        loop {
+            if self.tokens.peek() == Some(&Token::And) {
+                self.tokens.next();
+            } else if !self.implicit(Op::AND) {
+                break;
+            }
+            operands.push(self.operand(field)?);
+        }
+        Ok(join(operands, Query::And))
+    }
+
+    fn operand(&mut self, field: Option<Field>) -> Result<Query, String> {
+        match self.tokens.next() {
+            Some(Token::Word(word)) => Ok(Query::Term(field, word.to_lowercase())),
+            Some(Token::Field(field)) => self.operand(Some(field)),
+            Some(Token::Open) => {
+                let query = self.or(field)?;
+                match self.tokens.next() {
+                    Some(Token::Close) => Ok(query),
+                    _ => Err("missing )".to_string()),
+                }
+            }
+            Some(token) => Err(format!("unexpected {:?}", token)),
+            None => Err("query ended early".to_string()),
+        }
+    }
+}
+
+fn join(mut operands: Vec<Query>, op: fn(Vec<Query>) -> Query) -> Query {
+    if operands.len() == 1 {
+        operands.pop().unwrap()
+    } else {
+        op(operands)
+    }
+}
+
+/// A query with its terms resolved to columns of the combined term space.
+enum Compiled {
+    /// (column, boost) of the term in every field it's searched in that has
+    /// it, and how many fields it's searched in
+    Term(Vec<(usize, f32)>, usize),
+    And(Vec<Compiled>),
+    Or(Vec<Compiled>),
+}
+
+impl Compiled {
+    fn new(query: &Query, index: &FieldedIndex, boosts: &Boosts) -> Compiled {
+        match query {
+            Query::Term(field, term) => {
+                let fields = match field {
+                    Some(field) => vec![*field],
+                    None => Field::ALL.to_vec(),
+                };
+                let searched = fields.len();
+                Compiled::Term(
+                    fields
+                        .into_iter()
+                        .filter_map(|f| {
+                            let tidx = index.field(f).term_id(term)?;
+                            Some((index.offset(f) + tidx, boosts.get(f)))
+                        })
+                        .collect(),
+                    searched,
+                )
+            }
+            Query::And(qs) => {
+                Compiled::And(qs.iter().map(|q| Compiled::new(q, index, boosts)).collect())
+            }
+            Query::Or(qs) => {
+                Compiled::Or(qs.iter().map(|q| Compiled::new(q, index, boosts)).collect())
+            }
+        }
+    }
+
+    /// p-norm of the operands' scores, weights boosted and capped at 1
+    fn score(&self, doc: &ArrayView1<f32>, p: f32) -> f32 {
+        match self {
+            // a term in several fields is an or over them, fields without
+            // the term counting as 0 so terms found in few fields aren't favoured
+            Compiled::Term(columns, searched) => {
+                let weights = columns
+                    .iter()
+                    .map(|(col, boost)| (doc[*col] * boost).min(1.0));
+                or(weights, *searched, p)
+            }
+            Compiled::And(qs) => and(qs.iter().map(|q| q.score(doc, p)), qs.len(), p),
+            Compiled::Or(qs) => or(qs.iter().map(|q| q.score(doc, p)), qs.len(), p),
+        }
+    }
+}
+
+/// ((s1^p + ... + sm^p) / m)^(1/p)
+fn or<I: Iterator<Item = f32>>(scores: I, m: usize, p: f32) -> f32 {
+    if m == 0 {
+        return 0.0;
+    }
+    (scores.map(|s| s.powf(p)).sum::<f32>() / m as f32).powf(1.0 / p)
+}
+
+/// 1 - (((1-s1)^p + ... + (1-sm)^p) / m)^(1/p)
+fn and<I: Iterator<Item = f32>>(scores: I, m: usize, p: f32) -> f32 {
+    if m == 0 {
+        return 0.0;
+    }
+    1.0 - (scores.map(|s| (1.0 - s).powf(p)).sum::<f32>() / m as f32).powf(1.0 / p)
+}
+
+/// given a fielded query and the fielded index's combined weights, rank by
+/// the p-norm of each and/or over its operands
+///
+/// Unlike the flat `rank`, which normalizes by the size of the term space,
+/// every and/or is normalized by its number of operands, the p-norm model
+/// as proposed for nested queries. A term's weight in each field is
+/// multiplied by the field's boost, so a heading hit can outrank a body hit.
+pub fn rank_query(
+    query: &Query,
+    index: &FieldedIndex,
+    dt_matrix: &ArrayView2<f32>,
+    boosts: &Boosts,
+    p: f32,
+) -> (Vec<RankResult>, SearchStats) {
+    let start = Instant::now();
+    let compiled = Compiled::new(&query.stemmed(index), index, boosts);
+    let topk = dt_matrix
+        .axis_iter(Axis(0))
+        .into_par_iter()
+        .enumerate()
+        .map(|(doc_id, doc)| RankResult {
+            doc_id,
+            score: compiled.score(&doc, p),
+        })
+        .fold(
+            || TopK::new(K),
+            |mut topk, rr| {
+                topk.offer(rr);
+                topk
+            },
+        )
+        .reduce(|| TopK::new(K), TopK::merge);
+    let scoring = start.elapsed();
+
+    let start = Instant::now();
+    finish(topk, rayon::current_num_threads(), scoring, start)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        index::{
+            fields::{Boosts, Document, Field, FieldedIndex},
+            query::{rank_query, Query},
+        },
+        ranking::{normalization::Normalization, op::Op, scorer::Scorer},
+    };
+
+    fn term(field: Option<Field>, term: &str) -> Query {
+        Query::Term(field, term.to_string())
+    }
+
+    #[test]
+    fn parses_fields_and_precedence() {
+        let query = Query::parse("title:rust AND body:(company OR support)", Op::OR).unwrap();
+        assert_eq!(
+            query,
+            Query::And(vec![
+                term(Some(Field::Title), "rust"),
+                Query::Or(vec![
+                    term(Some(Field::Body), "company"),
+                    term(Some(Field::Body), "support")
+                ]),
+            ])
+        );
+        assert_eq!(
+            query.to_string(),
+            "(title:rust AND (body:company OR body:support))"
+        );
+
+        let query = Query::parse("a b AND c", Op::OR).unwrap();
+        assert_eq!(
+            query,
+            Query::Or(vec![
+                term(None, "a"),
+                Query::And(vec![term(None, "b"), term(None, "c")])
+            ])
+        );
+        let query = Query::parse("a b OR c", Op::AND).unwrap();
+        assert_eq!(
+            query,
+            Query::Or(vec![
+                Query::And(vec![term(None, "a"), term(None, "b")]),
+                term(None, "c")
+            ])
+        );
+        assert!(Query::parse("Rust Company", Op::OR)
+            .unwrap()
+            .is_flat(Op::OR));
+        assert!(!Query::parse("rust AND company", Op::OR)
+            .unwrap()
+            .is_flat(Op::OR));
+        assert!(!query.is_flat(Op::AND));
+        assert!(!Query::parse("title:rust", Op::OR).unwrap().is_flat(Op::OR));
+    }
+
+    #[test]
+    fn splits_terms_like_documents() {
+        assert_eq!(
+            Query::parse("rust, company-backed!", Op::OR).unwrap(),
+            Query::Or(vec![
+                term(None, "rust"),
+                term(None, "company"),
+                term(None, "backed")
+            ])
+        );
+        assert_eq!(
+            Query::parse("body:company-backed AND rust", Op::OR).unwrap(),
+            Query::And(vec![
+                Query::Or(vec![
+                    term(Some(Field::Body), "company"),
+                    term(Some(Field::Body), "backed")
+                ]),
+                term(None, "rust")
+            ])
+        );
+        assert_eq!(
+            Query::parse("rust - fast", Op::AND).unwrap(),
+            Query::And(vec![term(None, "rust"), term(None, "fast")])
+        );
+        assert!(Query::parse("--", Op::OR).is_err());
+    }
+
+    #[test]
+    fn colon_after_other_words_is_punctuation() {
+        assert_eq!(
+            Query::parse("note: rust", Op::OR),
+            Ok(Query::Or(vec![term(None, "note"), term(None, "rust")]))
+        );
+        assert_eq!(
+            Query::parse("footer:rust", Op::AND),
+            Ok(Query::And(vec![term(None, "footer"), term(None, "rust")]))
+        );
+    }
+
+    #[test]
+    fn terms_leave_out_ops() {
+        let query = Query::parse("rust and OR title:(fast AND safe)", Op::AND).unwrap();
+        assert_eq!(query.terms(), vec!["rust", "and", "fast", "safe"]);
+        let query = Query::parse("rust OR fast", Op::AND).unwrap();
+        assert_eq!(query.terms(), vec!["rust", "fast"]);
+    }
+
+    #[test]
+    fn rejects_bad_queries() {
+        assert!(Query::parse("title:", Op::OR).is_err());
+        assert!(Query::parse("(rust", Op::OR).is_err());
+        assert!(Query::parse("rust)", Op::OR).is_err());
+        assert!(Query::parse("rust AND", Op::OR).is_err());
+        assert!(Query::parse("", Op::OR).is_err());
+    }
+
+    fn index() -> FieldedIndex {
+        let mut index = FieldedIndex::new(Scorer::BM25, Normalization::None);
+        let docs = [
+            Document {
+                headings: "Support".to_string(),
+                body: "Companies help in many ways.".to_string(),
+                ..Document::default()
+            },
+            Document {
+                headings: "Performance".to_string(),
+                body: "We offer support to companies.".to_string(),
+                ..Document::default()
+            },
+            Document {
+                headings: "Performance".to_string(),
+                body: "Rust is fast.".to_string(),
+                ..Document::default()
+            },
+            Document::body("The weather is nice."),
+        ];
+        for doc in docs.iter() {
+            index.add(doc);
+        }
+        index.refresh();
+        index
+    }
+
+    #[test]
+    fn fields_scope_terms() {
+        let index = index();
+        let weights = index.weights();
+        let boosts = Boosts::default();
+        let ids = |q: &str| {
+            let query = Query::parse(q, Op::OR).unwrap();
+            let (results, _) = rank_query(&query, &index, &weights.view(), &boosts, 2.0);
+            results.iter().map(|r| r.doc_id).collect::<Vec<usize>>()
+        };
+        assert_eq!(ids("headings:support"), vec![0]);
+        assert_eq!(ids("body:support"), vec![1]);
+        assert_eq!(ids("headings:performance AND body:fast"), vec![2, 1]);
+        assert_eq!(ids("weather"), vec![3]);
+        assert!(ids("title:support").is_empty());
+    }
+
+    #[test]
+    fn terms_normalized_by_fields_searched() {
+        let index = index();
+        let weights = index.weights();
+        let boosts = Boosts::default();
+        let score = |q: &str, doc_id: usize| {
+            let query = Query::parse(q, Op::OR).unwrap();
+            let (results, _) = rank_query(&query, &index, &weights.view(), &boosts, 2.0);
+            results.iter().find(|r| r.doc_id == doc_id).unwrap().score
+        };
+        // weather is only ever in a body, but is still one of five fields
+        let body = score("body:weather", 3);
+        assert!((score("weather", 3) - body / 5f32.sqrt()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn boosts_reorder_fields() {
+        let index = index();
+        let weights = index.weights();
+        let query = Query::parse("support", Op::OR).unwrap();
+        let mut boosts = Boosts::default();
+        boosts.set(Field::Headings, 0.1);
+        let (results, _) = rank_query(&query, &index, &weights.view(), &boosts, 2.0);
+        assert_eq!(results[0].doc_id, 1);
+        boosts.set(Field::Headings, 10.0);
+        let (results, _) = rank_query(&query, &index, &weights.view(), &boosts, 2.0);
+        assert_eq!(results[0].doc_id, 0);
+    }
+}
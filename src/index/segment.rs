@@ -0,0 +1,119 @@
+use super::incremental::CollectionStats;
+use crate::ranking::{
+    normalization::Normalization, op::Op, rank::score_n, rank_result::RankResult, scorer::Scorer,
+    top_k::TopK,
+};
+use ndarray::{s, Array2, ArrayView1, ArrayView2};
+use std::collections::{HashMap, HashSet};
+
+/// A document as a segment stores it, by global doc and term ids
+#[derive(Debug, Clone, PartialEq)]
+pub struct SegmentDoc {
+    pub doc_id: usize,
+    //---------------(term , freq )
+    pub terms: Vec<(usize, usize)>,
+}
+
+/// An immutable slice of the index.
+///
+/// Rows are documents in ascending doc id order and columns are global term
+/// ids, up to the vocabulary size when the segment was written. Weights are
+/// computed once, from the collection statistics at that time, and only
+/// change when the segment is merged into a new one.
+#[derive(Debug)]
+pub struct Segment {
+    id: u64,
+    docs: Vec<SegmentDoc>,
+    //-------------------------(row  , freq )
+    postings: HashMap<usize, Vec<(usize, usize)>>,
+    weights: Array2<f32>,
+}
+
+impl Segment {
+    /// weight docs with the given collection statistics, `doc_freq` indexed by term id
+    pub fn build(
+        id: u64,
+        mut docs: Vec<SegmentDoc>,
+        num_terms: usize,
+        doc_freq: &[usize],
+        stats: &CollectionStats,
+        scorer: Scorer,
+        normalization: Normalization,
+    ) -> Segment {
+        docs.sort_by_key(|d| d.doc_id);
+        let mut postings: HashMap<usize, Vec<(usize, usize)>> = HashMap::new();
+        let mut weights = Array2::zeros((docs.len(), num_terms));
+        for (row, doc) in docs.iter().enumerate() {
+            for (tidx, freq) in doc.terms.iter() {
+                postings.entry(*tidx).or_default().push((row, *freq));
+                weights[[row, *tidx]] = scorer.weight(
+                    *freq,
+                    doc.terms.len(),
+                    doc_freq[*tidx],
+                    stats.docs,
+                    stats.avg_doc_len,
+                );
+            }
+        }
+        // max is over this segment only, unlike the others it isn't comparable across segments
+        normalization.apply(&mut weights);
+        Segment {
+            id,
+            docs,
+            postings,
+            weights,
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn num_docs(&self) -> usize {
+        self.docs.len()
+    }
+
+    pub fn docs(&self) -> &[SegmentDoc] {
+        &self.docs
+    }
+
+    pub fn doc(&self, doc_id: usize) -> Option<&SegmentDoc> {
+        self.docs
+            .binary_search_by_key(&doc_id, |d| d.doc_id)
+            .ok()
+            .map(|row| &self.docs[row])
+    }
+
+    /// (row, freq) of every document in the segment containing the term
+    pub fn postings(&self, term_id: usize) -> &[(usize, usize)] {
+        self.postings.get(&term_id).map_or(&[], |p| p.as_slice())
+    }
+
+    pub fn weights(&self) -> ArrayView2<'_, f32> {
+        self.weights.view()
+    }
+
+    /// collect the top k of this segment, skipping deleted docs
+    ///
+    /// `query` is embedded in the whole current term space, which may have grown
+    /// since the segment was written, so scoring is over all of its dims
+    pub fn search(
+        &self,
+        query: &ArrayView1<f32>,
+        op: &Op,
+        p: f32,
+        deleted: &HashSet<usize>,
+        topk: &mut TopK,
+    ) {
+        let cols = self.weights.ncols().min(query.dim());
+        let q = query.slice(s![..cols]);
+        for (row, doc) in self.weights.rows().into_iter().enumerate() {
+            let doc_id = self.docs[row].doc_id;
+            if deleted.contains(&doc_id) {
+                continue;
+            }
+            let score = score_n(&q, &doc.slice(s![..cols]), op, p, query.dim());
+            topk.offer(RankResult { doc_id, score });
+        }
+    }
+}
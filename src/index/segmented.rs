@@ -0,0 +1,529 @@
+use super::{
+    analyze::{stem, term_freqs},
+    incremental::CollectionStats,
+    merge::{MergePolicy, SegmentInfo},
+    segment::{Segment, SegmentDoc},
+};
+use crate::ranking::{
+    normalization::Normalization, op::Op, rank::K, rank_result::RankResult, scorer::Scorer,
+    stats::SearchStats, top_k::TopK,
+};
+use ndarray::{Array1, ArrayView1};
+use rayon::prelude::*;
+use rust_stemmers::{Algorithm, Stemmer};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::Instant,
+};
+
+/// documents buffered in the live segment before it's written out
+pub static MAX_LIVE: usize = 1000;
+
+struct PendingMerge {
+    inputs: Vec<u64>,
+    /// tombstones the merge purged, known when it started
+    purged: HashSet<usize>,
+    handle: JoinHandle<Segment>,
+}
+
+/// An index made of immutable segments plus one small live segment.
+///
+/// New documents go to the live segment, which is written out as an
+/// immutable segment once it holds `max_live` documents. Deletes of written
+/// documents are tombstones skipped at query time, until a merge rewrites
+/// their segment without them. Merges run on a background thread, chosen by
+/// the `MergePolicy`, and are swapped in by `poll_merges`.
+///
+/// Unlike `IncrementalIndex` an update gives the document a new doc id.
+pub struct SegmentedIndex {
+    scorer: Scorer,
+    normalization: Normalization,
+    max_live: usize,
+    policy: MergePolicy,
+    stemmer: Stemmer,
+    vocab: HashMap<String, usize>,
+    terms: Vec<String>,
+    /// live documents containing each term, across every segment
+    doc_freq: Vec<usize>,
+    next_doc_id: usize,
+    next_segment_id: u64,
+    live_docs: usize,
+    total_len: usize,
+    segments: Vec<Arc<Segment>>,
+    deleted: HashSet<usize>,
+    live: Vec<SegmentDoc>,
+    /// live documents as a segment, rebuilt by refresh
+    live_segment: Option<Segment>,
+    merging: Option<PendingMerge>,
+}
+
+impl SegmentedIndex {
+    pub fn new(scorer: Scorer, normalization: Normalization) -> SegmentedIndex {
+        SegmentedIndex::with_policy(scorer, normalization, MAX_LIVE, MergePolicy::default())
+    }
+
+    pub fn with_policy(
+        scorer: Scorer,
+        normalization: Normalization,
+        max_live: usize,
+        policy: MergePolicy,
+    ) -> SegmentedIndex {
+        SegmentedIndex {
+            scorer,
+            normalization,
+            max_live: max_live.max(1),
+            policy,
+            stemmer: Stemmer::create(Algorithm::English),
+            vocab: HashMap::new(),
+            terms: Vec::new(),
+            doc_freq: Vec::new(),
+            next_doc_id: 0,
+            next_segment_id: 0,
+            live_docs: 0,
+            total_len: 0,
+            segments: Vec::new(),
+            deleted: HashSet::new(),
+            live: Vec::new(),
+            live_segment: None,
+            merging: None,
+        }
+    }
+
+    /// documents that haven't been deleted
+    pub fn num_docs(&self) -> usize {
+        self.live_docs
+    }
+
+    pub fn num_terms(&self) -> usize {
+        self.terms.len()
+    }
+
+    /// written segments, not counting the live one
+    pub fn num_segments(&self) -> usize {
+        self.segments.len()
+    }
+
+    pub fn segment_infos(&self) -> Vec<SegmentInfo> {
+        self.segments
+            .iter()
+            .map(|s| SegmentInfo {
+                id: s.id(),
+                docs: s.num_docs(),
+                deleted: s
+                    .docs()
+                    .iter()
+                    .filter(|d| self.deleted.contains(&d.doc_id))
+                    .count(),
+            })
+            .collect()
+    }
+
+    pub fn term_id(&self, term: &str) -> Option<usize> {
+        self.vocab.get(term).copied()
+    }
+
+    pub fn stats(&self) -> CollectionStats {
+        CollectionStats {
+            docs: self.live_docs,
+            avg_doc_len: if self.live_docs == 0 {
+                0.0
+            } else {
+                self.total_len as f32 / self.live_docs as f32
+            },
+        }
+    }
+
+    pub fn is_deleted(&self, doc_id: usize) -> bool {
+        doc_id >= self.next_doc_id
+            || self.deleted.contains(&doc_id)
+            || (self.live.iter().all(|d| d.doc_id != doc_id)
+                && self.segments.iter().all(|s| s.doc(doc_id).is_none()))
+    }
+
+    /// index a document, returning its doc id
+    pub fn add(&mut self, text: &str) -> usize {
+        let doc_id = self.next_doc_id;
+        self.next_doc_id += 1;
+        let terms = self.intern(text);
+        for (tidx, _) in terms.iter() {
+            self.doc_freq[*tidx] += 1;
+        }
+        self.live_docs += 1;
+        self.total_len += terms.len();
+        self.live.push(SegmentDoc { doc_id, terms });
+        self.live_segment = None;
+        if self.live.len() >= self.max_live {
+            self.flush();
+        }
+        doc_id
+    }
+
+    /// delete a document and index text in its place, returning the new doc id
+    pub fn update(&mut self, doc_id: usize, text: &str) -> Option<usize> {
+        if !self.delete(doc_id) {
+            return None;
+        }
+        Some(self.add(text))
+    }
+
+    /// delete a document, false if it was already deleted or unknown
+    pub fn delete(&mut self, doc_id: usize) -> bool {
+        if doc_id >= self.next_doc_id || self.deleted.contains(&doc_id) {
+            return false;
+        }
+        let terms = if let Some(pos) = self.live.iter().position(|d| d.doc_id == doc_id) {
+            // never written, so it can just be dropped
+            self.live_segment = None;
+            self.live.remove(pos).terms
+        } else if let Some(doc) = self.segments.iter().find_map(|s| s.doc(doc_id)) {
+            let terms = doc.terms.clone();
+            self.deleted.insert(doc_id);
+            terms
+        } else {
+            return false;
+        };
+        for (tidx, _) in terms.iter() {
+            self.doc_freq[*tidx] -= 1;
+        }
+        self.live_docs -= 1;
+        self.total_len -= terms.len();
+        true
+    }
+
+    /// rebuild the live segment so searches see recent adds and deletes
+    pub fn refresh(&mut self) {
+        if self.live_segment.is_none() && !self.live.is_empty() {
+            self.live_segment = Some(self.build(u64::MAX, self.live.clone()));
+        }
+    }
+
+    /// write the live documents out as an immutable segment
+    pub fn flush(&mut self) {
+        if self.live.is_empty() {
+            return;
+        }
+        let docs = std::mem::take(&mut self.live);
+        let id = self.next_segment_id;
+        self.next_segment_id += 1;
+        let segment = self.build(id, docs);
+        self.segments.push(Arc::new(segment));
+        self.live_segment = None;
+        self.maybe_merge();
+    }
+
+    /// start a background merge if the policy picks one and none is running
+    pub fn maybe_merge(&mut self) -> bool {
+        if self.merging.is_some() {
+            return false;
+        }
+        match self.policy.select(&self.segment_infos()) {
+            Some(inputs) => {
+                self.start_merge(inputs);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// write out the live documents and merge everything into a single segment
+    pub fn force_merge(&mut self) {
+        self.wait_for_merges();
+        self.live_segment = None;
+        let docs = std::mem::take(&mut self.live);
+        if !docs.is_empty() {
+            let id = self.next_segment_id;
+            self.next_segment_id += 1;
+            let segment = self.build(id, docs);
+            self.segments.push(Arc::new(segment));
+        }
+        if !self.segments.is_empty() {
+            self.start_merge(self.segments.iter().map(|s| s.id()).collect());
+            self.wait_for_merges();
+        }
+    }
+
+    fn start_merge(&mut self, inputs: Vec<u64>) {
+        let segments: Vec<Arc<Segment>> = self
+            .segments
+            .iter()
+            .filter(|s| inputs.contains(&s.id()))
+            .cloned()
+            .collect();
+        let purged: HashSet<usize> = segments
+            .iter()
+            .flat_map(|s| s.docs().iter().map(|d| d.doc_id))
+            .filter(|id| self.deleted.contains(id))
+            .collect();
+
+        let id = self.next_segment_id;
+        self.next_segment_id += 1;
+        // weights are recomputed with the statistics as they are now
+        let (num_terms, doc_freq, stats) = (self.terms.len(), self.doc_freq.clone(), self.stats());
+        let (scorer, normalization) = (self.scorer, self.normalization);
+        let skip = purged.clone();
+        let handle = thread::spawn(move || {
+            let docs: Vec<SegmentDoc> = segments
+                .iter()
+                .flat_map(|s| s.docs().iter())
+                .filter(|d| !skip.contains(&d.doc_id))
+                .cloned()
+                .collect();
+            Segment::build(
+                id,
+                docs,
+                num_terms,
+                &doc_freq,
+                &stats,
+                scorer,
+                normalization,
+            )
+        });
+        self.merging = Some(PendingMerge {
+            inputs,
+            purged,
+            handle,
+        });
+    }
+
+    /// swap in a finished background merge, returning whether one was
+    pub fn poll_merges(&mut self) -> bool {
+        match &self.merging {
+            Some(merge) if merge.handle.is_finished() => {
+                self.install_merge();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// block until no merges are running or due
+    pub fn wait_for_merges(&mut self) {
+        while self.merging.is_some() {
+            self.install_merge();
+            self.maybe_merge();
+        }
+    }
+
+    fn install_merge(&mut self) {
+        if let Some(merge) = self.merging.take() {
+            let merged = merge.handle.join().expect("merge thread panicked");
+            self.segments.retain(|s| !merge.inputs.contains(&s.id()));
+            self.segments.push(Arc::new(merged));
+            // deletes that came in while merging are still tombstones
+            self.deleted.retain(|id| !merge.purged.contains(id));
+        }
+    }
+
+    /// embed query terms into the term space, unknown terms are ignored
+    pub fn embed<'a, I: IntoIterator<Item = &'a str>>(&self, terms: I) -> Array1<f32> {
+        let mut query: Array1<f32> = Array1::zeros(self.terms.len());
+        for term in terms {
+            if let Some(tidx) = self.term_id(term) {
+                query[tidx] = 1.0;
+            }
+        }
+        query
+    }
+
+    /// stem a word the same way documents are
+    pub fn stem(&self, word: &str) -> String {
+        stem(word, &self.stemmer)
+    }
+
+    /// rank every segment in parallel by vboo op, call `refresh` first to see recent changes
+    pub fn search(
+        &self,
+        query: &ArrayView1<f32>,
+        op: &Op,
+        p: f32,
+    ) -> (Vec<RankResult>, SearchStats) {
+        let start = Instant::now();
+        let segments: Vec<&Segment> = self
+            .segments
+            .iter()
+            .map(|s| s.as_ref())
+            .chain(self.live_segment.iter())
+            .collect();
+        let topks: Vec<TopK> = segments
+            .par_iter()
+            .map(|segment| {
+                let mut topk = TopK::new(K);
+                segment.search(query, op, p, &self.deleted, &mut topk);
+                topk
+            })
+            .collect();
+        let scoring = start.elapsed();
+
+        let start = Instant::now();
+        let topk = topks.into_iter().fold(TopK::new(K), TopK::merge);
+        let stats = SearchStats {
+            docs_scanned: topk.offered(),
+            docs_matched: topk.matched(),
+            threads: rayon::current_num_threads(),
+            scoring,
+            ..SearchStats::default()
+        };
+        let results = topk.into_sorted_vec();
+        (
+            results,
+            SearchStats {
+                merging: start.elapsed(),
+                ..stats
+            },
+        )
+    }
+
+    fn build(&self, id: u64, docs: Vec<SegmentDoc>) -> Segment {
+        Segment::build(
+            id,
+            docs,
+            self.terms.len(),
+            &self.doc_freq,
+            &self.stats(),
+            self.scorer,
+            self.normalization,
+        )
+    }
+
+    /// term ids and frequencies for text, adding unseen terms to the vocabulary
+    fn intern(&mut self, text: &str) -> Vec<(usize, usize)> {
+        term_freqs(text, &self.stemmer)
+            .into_iter()
+            .map(|(term, freq)| {
+                let tidx = match self.vocab.get(&term) {
+                    Some(tidx) => *tidx,
+                    None => {
+                        let tidx = self.terms.len();
+                        self.vocab.insert(term.clone(), tidx);
+                        self.terms.push(term);
+                        self.doc_freq.push(0);
+                        tidx
+                    }
+                };
+                (tidx, freq)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        index::{incremental::IncrementalIndex, merge::MergePolicy, segmented::SegmentedIndex},
+        ranking::{normalization::Normalization, op::Op, rank::rank, scorer::Scorer},
+    };
+
+    fn sents() -> Vec<String> {
+        (0..60)
+            .map(|i| match i % 4 {
+                0 => format!("rust project {} receives support from companies", i),
+                1 => format!("rust is fast number {}", i),
+                2 => format!("companies use rust {} in production", i),
+                _ => format!("the weather {} is nice", i),
+            })
+            .collect()
+    }
+
+    fn segmented(max_live: usize) -> SegmentedIndex {
+        let policy = MergePolicy {
+            merge_factor: 3,
+            ..MergePolicy::default()
+        };
+        SegmentedIndex::with_policy(Scorer::BM25, Normalization::L2, max_live, policy)
+    }
+
+    #[test]
+    fn same_ranking_as_one_matrix() {
+        let mut index = segmented(7);
+        let mut single = IncrementalIndex::new(Scorer::BM25, Normalization::L2);
+        for sent in sents().iter() {
+            index.add(sent);
+            single.add(sent);
+        }
+        // merge everything into one segment weighted with final statistics
+        index.force_merge();
+        single.reweight();
+        assert_eq!(index.num_segments(), 1);
+        for op in [Op::AND, Op::OR] {
+            let terms = ["rust", "compani", "support"];
+            let (expected, _) = rank(&single.embed(terms).view(), &single.weights(), &op, 2.0);
+            let (got, _) = index.search(&index.embed(terms).view(), &op, 2.0);
+            let ids = |r: &[crate::ranking::rank_result::RankResult]| {
+                r.iter().map(|r| r.doc_id).collect::<Vec<usize>>()
+            };
+            assert_eq!(ids(&expected), ids(&got));
+            for (e, g) in expected.iter().zip(got.iter()) {
+                assert!((e.score - g.score).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn fans_out_over_segments_and_live() {
+        let mut index = segmented(5);
+        for sent in sents().iter().take(12) {
+            index.add(sent);
+        }
+        // 2 written segments of 5 and 2 docs still live
+        assert_eq!(index.num_segments(), 2);
+        let query = index.embed(["weather"]);
+        let (before, _) = index.search(&query.view(), &Op::OR, 2.0);
+        assert_eq!(before.len(), 2);
+        index.refresh();
+        let (after, stats) = index.search(&query.view(), &Op::OR, 2.0);
+        assert_eq!(after.len(), 3);
+        assert_eq!(stats.docs_scanned, 12);
+        assert!(after.iter().any(|r| r.doc_id == 11));
+    }
+
+    #[test]
+    fn deletes_are_purged_by_merges() {
+        let mut index = segmented(4);
+        for sent in sents().iter().take(8) {
+            index.add(sent);
+        }
+        assert_eq!(index.num_segments(), 2);
+        // weather docs are 3 and 7
+        assert!(index.delete(3));
+        assert!(!index.delete(3));
+        assert!(index.is_deleted(3));
+        assert_eq!(index.num_docs(), 7);
+        let query = index.embed(["weather"]);
+        let (results, _) = index.search(&query.view(), &Op::OR, 2.0);
+        assert_eq!(
+            results.iter().map(|r| r.doc_id).collect::<Vec<_>>(),
+            vec![7]
+        );
+
+        // one of four deleted is over the 20% the policy allows
+        assert!(index.maybe_merge());
+        index.wait_for_merges();
+        assert!(index.segment_infos().iter().all(|s| s.deleted == 0));
+        assert!(index.deleted.is_empty());
+        let (results, stats) = index.search(&query.view(), &Op::OR, 2.0);
+        assert_eq!(
+            results.iter().map(|r| r.doc_id).collect::<Vec<_>>(),
+            vec![7]
+        );
+        assert_eq!(stats.docs_scanned, 7);
+    }
+
+    #[test]
+    fn update_moves_doc() {
+        let mut index = segmented(100);
+        index.add("companies use rust");
+        index.add("support the project");
+        let doc = index.add("the weather is nice");
+        let new_doc = index.update(doc, "rust is nice").unwrap();
+        assert_ne!(doc, new_doc);
+        assert!(index.is_deleted(doc));
+        assert_eq!(index.update(doc, "again"), None);
+        index.refresh();
+        let (results, _) = index.search(&index.embed(["weather"]).view(), &Op::OR, 2.0);
+        assert!(results.is_empty());
+        let (results, _) = index.search(&index.embed(["nice"]).view(), &Op::OR, 2.0);
+        assert_eq!(results.iter().map(|r| r.doc_id).collect::<Vec<_>>(), vec![new_doc]);
+    }
+}
@@ -0,0 +1,184 @@
+use super::query::Query;
+use std::{collections::HashMap, str::FromStr};
+
+/// Synonyms in solr's format, applied to queries term by term.
+///
+/// A line `a, b, c` makes each of the words stand for all of them, and
+/// `a, b => c, d` replaces `a` and `b` with `c` and `d`. Blank lines and
+/// lines starting with `#` are skipped. Synonyms can be phrases, searched
+/// for as an and of their words, but queries are matched a word at a time
+/// so phrases on the left of `=>` never match.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Synonyms {
+    /// lowercased word -> what it stands for, each a phrase of words
+    rules: HashMap<String, Vec<Vec<String>>>,
+}
+
+impl FromStr for Synonyms {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Synonyms, Self::Err> {
+        let mut synonyms = Synonyms::default();
+        for (number, line) in input.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (from, to) = match line.split_once("=>") {
+                Some((from, to)) => (phrases(from), phrases(to)),
+                None => (phrases(line), phrases(line)),
+            };
+            if from.is_empty() || to.is_empty() || line.matches("=>").count() > 1 {
+                return Err(format!("bad synonyms on line {}: {}", number + 1, line));
+            }
+            for word in from.iter().filter(|p| p.len() == 1).map(|p| &p[0]) {
+                let rule = synonyms.rules.entry(word.clone()).or_default();
+                for phrase in to.iter() {
+                    if !rule.contains(phrase) {
+                        rule.push(phrase.clone());
+                    }
+                }
+            }
+        }
+        Ok(synonyms)
+    }
+}
+
+impl Synonyms {
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// every term with synonyms replaced by an or of them, keeping its field
+    ///
+    /// Terms and synonyms are compared by their stems, so `companies`
+    /// matches a rule for `company`, and rules for words with the same stem
+    /// are merged, in the order of their words. Synonyms aren't expanded in
+    /// turn.
+    pub fn expand<F: Fn(&str) -> String>(&self, query: &Query, stem: F) -> Query {
+        let mut words: Vec<&String> = self.rules.keys().collect();
+        words.sort();
+        let mut stemmed: HashMap<String, Vec<Vec<String>>> = HashMap::new();
+        for word in words {
+            let rule = stemmed.entry(stem(word)).or_default();
+            for phrase in self.rules[word].iter() {
+                if !rule.contains(phrase) {
+                    rule.push(phrase.clone());
+                }
+            }
+        }
+        self.expand_with(query, &|term| stemmed.get(&stem(term)))
+    }
+
+    fn expand_with<'a, L>(&self, query: &Query, lookup: &L) -> Query
+    where
+        L: Fn(&str) -> Option<&'a Vec<Vec<String>>>,
+    {
+        match query {
+            Query::Term(field, term) => match lookup(term) {
+                None => query.clone(),
+                Some(phrases) => {
+                    let mut alternatives: Vec<Query> = phrases
+                        .iter()
+                        .map(|phrase| {
+                            let mut words: Vec<Query> = phrase
+                                .iter()
+                                .map(|w| Query::Term(*field, w.clone()))
+                                .collect();
+                            match words.len() {
+                                1 => words.pop().unwrap(),
+                                _ => Query::And(words),
+                            }
+                        })
+                        .collect();
+                    match alternatives.len() {
+                        1 => alternatives.pop().unwrap(),
+                        _ => Query::Or(alternatives),
+                    }
+                }
+            },
+            Query::And(qs) => Query::And(qs.iter().map(|q| self.expand_with(q, lookup)).collect()),
+            Query::Or(qs) => Query::Or(qs.iter().map(|q| self.expand_with(q, lookup)).collect()),
+        }
+    }
+}
+
+/// comma separated phrases, each lowercased and split into words
+fn phrases(list: &str) -> Vec<Vec<String>> {
+    list.split(',')
+        .map(|p| {
+            p.split_whitespace()
+                .map(|w| w.to_lowercase())
+                .collect::<Vec<String>>()
+        })
+        .filter(|p| !p.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_stemmers::{Algorithm, Stemmer};
+
+    use crate::{
+        index::{analyze::stem, query::Query, synonyms::Synonyms},
+        ranking::op::Op,
+    };
+
+    static SYNONYMS: &str = "
+# companies
+company, firm, Organization
+business => company, firm
+usa, united states
+colour => color
+";
+
+    fn expand(synonyms: &Synonyms, query: &str) -> String {
+        let stemmer = Stemmer::create(Algorithm::English);
+        let query = Query::parse(query, Op::OR).unwrap();
+        synonyms.expand(&query, |w| stem(w, &stemmer)).to_string()
+    }
+
+    #[test]
+    fn expands_terms_to_or_clauses() {
+        let synonyms: Synonyms = SYNONYMS.parse().unwrap();
+        assert_eq!(
+            expand(&synonyms, "rust company support"),
+            "(rust OR (company OR firm OR organization) OR support)"
+        );
+        // matched by stem, in the field it was in
+        assert_eq!(
+            expand(&synonyms, "title:companies AND rust"),
+            "((title:company OR title:firm OR title:organization) AND rust)"
+        );
+        // replaced, and what it's replaced with isn't expanded again
+        assert_eq!(expand(&synonyms, "business"), "(company OR firm)");
+        assert_eq!(expand(&synonyms, "colour"), "color");
+        assert_eq!(expand(&synonyms, "usa"), "(usa OR (united AND states))");
+        assert_eq!(expand(&synonyms, "united"), "united");
+    }
+
+    #[test]
+    fn merges_rules_sharing_a_stem() {
+        // each parse hashes its rules in another order
+        for _ in 0..10 {
+            let synonyms: Synonyms = "company, firm\ncompanies, enterprise, firm"
+                .parse()
+                .unwrap();
+            assert_eq!(
+                expand(&synonyms, "company"),
+                "(companies OR enterprise OR firm OR company)"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_bad_lines() {
+        assert!("a =>".parse::<Synonyms>().is_err());
+        assert!("=> b".parse::<Synonyms>().is_err());
+        assert!("a => b => c".parse::<Synonyms>().is_err());
+        assert!(" # only a comment\n\n"
+            .parse::<Synonyms>()
+            .unwrap()
+            .is_empty());
+    }
+}
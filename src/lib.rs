@@ -1 +1,3 @@
+pub mod fetch;
+pub mod index;
 pub mod ranking;
@@ -1,23 +1,60 @@
 use colored::Colorize;
-use ndarray::{Array, Array1, Array2};
-use ranking::{
-    op::Op,
-    rank::{rank, rank_parallel},
-    scorer::Scorer,
-};
+use ndarray::{ArrayView1, ArrayView2};
 use rust_stemmers::{Algorithm, Stemmer};
 use std::{
-    collections::{HashMap, HashSet},
+    collections::HashSet,
     fmt::Debug,
-    time::Instant,
+    fs,
+    io::{self, BufRead, Write},
+    path::PathBuf,
+    time::{Instant, SystemTime},
 };
 use structopt::StructOpt;
 use unicode_segmentation::UnicodeSegmentation;
-use webpage::{Webpage, WebpageOptions};
-
-use crate::ranking::rank::rank_parallel_skim;
+use vboo::fetch::{
+    cache::Cache,
+    crawl::{crawl, parse_max_pages, Crawl},
+    Page,
+};
+use vboo::index::{
+    analyze::stem,
+    context::context,
+    dedup::{Dedup, Duplicates},
+    fields::{Boosts, Document, FieldedIndex},
+    gutenberg::{self, is_gutenberg},
+    html::extract,
+    incremental::IncrementalIndex,
+    mmap::MmapIndex,
+    query::{rank_query, Query},
+    synonyms::Synonyms,
+};
+use vboo::ranking::{
+    compare::{compare, RankConfig},
+    explain::{explain, Explanation},
+    feedback::{expand, rocchio, Expansion, Marks, Rocchio},
+    gather::rank_gathered,
+    group::{rank_grouped, Aggregate},
+    normalization::Normalization,
+    op::Op,
+    prefilter::{rank_prefiltered, Prefilter},
+    pruned::rank_pruned,
+    rank::{parse_p, rank, rank_parallel, rank_parallel_skim},
+    rank_result::RankResult,
+    retriever::Model,
+    scorer::Scorer,
+};
+use webpage::HTML;
 
-mod ranking;
+/// progress and timings, on stderr when stdout is left to json
+macro_rules! status {
+    ($json:expr, $($arg:tt)*) => {
+        if $json {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
 
 #[derive(Debug, StructOpt)]
 #[structopt(
@@ -28,105 +65,364 @@ struct Opt {
     /// Set query op
     #[structopt(short, long, default_value = "or")]
     op: Op,
+    /// Set p for the p-norm, 1 treats and/or the same
+    #[structopt(long, default_value = "2.0", parse(try_from_str = parse_p))]
+    p: f32,
     /// Set scorer used to weight terms in document term matrix
     #[structopt(short, long, default_value = "bm25")]
     scorer: Scorer,
+    /// Set normalization applied to document term matrix weights (none, max, l2)
+    #[structopt(long, default_value = "none")]
+    norm: Normalization,
     /// Set source page
     #[structopt(short, long, default_value = "http://www.rust-lang.org/en-US/")]
     page: String,
-    /// Set query string
+    /// Crawl pages on the source page's site, following links from it
+    #[structopt(long)]
+    crawl: bool,
+    /// With crawl, follow links at most this many pages away from the source page
+    #[structopt(long, default_value = "2")]
+    depth: usize,
+    /// With crawl, fetch at most this many pages
+    #[structopt(long, default_value = "20", parse(try_from_str = parse_max_pages))]
+    max_pages: usize,
+    /// Keep downloaded pages in this directory, `$XDG_CACHE_HOME/vboo` by default
+    #[structopt(long, parse(from_os_str))]
+    cache: Option<PathBuf>,
+    /// Only use pages already in the cache, never downloading
+    #[structopt(long)]
+    offline: bool,
+    /// Set query string, terms can be scoped to a field like
+    /// `title:rust AND body:(company OR support)`
     #[structopt(short, long, default_value = "rust company support")]
     query: String,
+    /// Multiply fields' term weights like `title=3,headings=2`,
+    /// fields are title, headings, body, anchors and meta
+    #[structopt(long)]
+    boost: Option<Boosts>,
+    /// Expand query terms with synonyms from this file, lines like `a, b, c` or `a => b`
+    #[structopt(long, parse(from_os_str))]
+    synonyms: Option<PathBuf>,
     /// Recreate test data using current query and page
     #[structopt(short, long)]
     fixture: bool,
-    /// Exhaustively check op rather than use options
+    /// Rank with only this model: pnorm, the pnorm-skim, pnorm-sequential,
+    /// pnorm-pruned and pnorm-gathered ways of ranking it, or the boolean,
+    /// cosine and bm25 baselines
+    #[structopt(short, long)]
+    model: Option<Model>,
+    /// Compare rankings across configs like `model=cosine,op=and,p=1,scorer=tfidf,norm=max`,
+    /// with no configs compares or against and using the other options
+    #[structopt(short, long)]
+    compare: Option<Vec<RankConfig>>,
+    /// Load a saved index from this directory instead of the page,
+    /// saving one there first if it doesn't exist yet
+    #[structopt(long, parse(from_os_str))]
+    index: Option<PathBuf>,
+    /// With and, also rank only docs holding at least this many query terms,
+    /// all of them by default
+    #[structopt(long)]
+    min_matched: Option<usize>,
+    /// With and, score every doc when fewer docs hold enough query terms
+    #[structopt(long, default_value = "10")]
+    min_candidates: usize,
+    /// Expand the query with the strongest terms of its top results and rank again
+    #[structopt(long)]
+    expand: bool,
+    /// With expand, top results taken as relevant
+    #[structopt(long, default_value = "5")]
+    expand_docs: usize,
+    /// With expand, new terms added at most
+    #[structopt(long, default_value = "5")]
+    expand_terms: usize,
+    /// With expand, weight of the strongest new term, the others weighing less
+    #[structopt(long, default_value = "0.5")]
+    expand_weight: f32,
+    /// Mark results as relevant or not and rank again, until an empty line
     #[structopt(short, long)]
-    compare: bool,
+    interactive: bool,
+    /// Show how each query term adds to the top results' scores
+    #[structopt(long)]
+    explain: bool,
+    /// With explain, print only the explanations of the parallel ranking, as json
+    #[structopt(long)]
+    json: bool,
+    /// Show this many sentences from the same page before and after each result
+    #[structopt(long, default_value = "0")]
+    context: usize,
+    /// Collapse near-duplicate results into the first of them, with how many there are
+    #[structopt(long)]
+    collapse: bool,
+    /// With collapse, estimated jaccard similarity of word shingles at which docs are near-duplicates
+    #[structopt(long, default_value = "0.8")]
+    dedup_threshold: f32,
+    /// Rank the source pages by their sentences' scores, taking their max, sum
+    /// or mean of the best n as in `mean:3`
+    #[structopt(long)]
+    group: Option<Aggregate>,
+    /// With group, best sentences shown under each page
+    #[structopt(long, default_value = "3")]
+    group_passages: usize,
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// List cached pages with when they were fetched
+    CacheList,
+    /// Remove cached pages, all of them unless urls are given
+    CachePurge { urls: Vec<String> },
 }
 
 fn main() -> Result<(), &'static str> {
     let opt = Opt::from_args();
+    if opt.json && (!opt.explain || opt.compare.is_some() || opt.model.is_some()) {
+        return Err("json is only for explain, without compare or model");
+    }
+    match &opt.command {
+        Some(Command::CacheList) => {
+            let entries = match open_cache(&opt)?.entries() {
+                Ok(entries) => entries,
+                Err(e) => {
+                    eprintln!("Could not read cache: {}", e);
+                    return Err("unreadable cache");
+                }
+            };
+            for entry in entries.iter() {
+                let age = SystemTime::now()
+                    .duration_since(entry.fetched)
+                    .unwrap_or_default();
+                let validator = entry.etag.as_ref().or(entry.last_modified.as_ref());
+                println!(
+                    "{} - {} bytes, fetched {}s ago, {}",
+                    entry.url,
+                    entry.size,
+                    age.as_secs(),
+                    validator.map_or("not revalidatable", |v| v.as_str())
+                );
+            }
+            println!("{} cached pages", entries.len());
+            return Ok(());
+        }
+        Some(Command::CachePurge { urls }) => {
+            let urls: Vec<&str> = urls.iter().map(|u| u.as_str()).collect();
+            let removed = match open_cache(&opt)?.purge(&urls) {
+                Ok(removed) => removed,
+                Err(e) => {
+                    eprintln!("Could not purge cache: {}", e);
+                    return Err("unreadable cache");
+                }
+            };
+            println!("Removed {} cached pages", removed);
+            return Ok(());
+        }
+        None => {}
+    }
 
-    // get some content to process
     let start = Instant::now();
-    let wpage = Webpage::from_url(opt.page.as_str(), WebpageOptions::default())
-        .expect("Could not read from URL");
-    let duration = start.elapsed();
-    println!("Downloading page elapsed: {:?}", duration);
-    let text = wpage.html.text_content;
-    // in many cases (project gutenberg) it makes sense to remove these entirely
-    let text = text.replace('\n', " ");
+    let loaded = match &opt.index {
+        Some(dir) if dir.exists() => {
+            Some(MmapIndex::open(dir).expect("Could not open saved index"))
+        }
+        _ => None,
+    };
+    // a saved index ranks with the weights it was saved with, whatever the flags say
+    if let Some(model) = opt.model {
+        let (scorer, norm) = match &loaded {
+            Some(index) => (index.scorer(), index.normalization()),
+            None => (opt.scorer, opt.norm),
+        };
+        if let Err(e) = model.check(scorer, norm) {
+            eprintln!("Could not rank using {}: {}", model, e);
+            return Err("invalid model");
+        }
+    }
 
-    let start = Instant::now();
-    // process text into sentences
-    let sents = text
-        .unicode_sentences()
-        .map(|s| trim_clean(s))
-        .collect::<Vec<&str>>();
-    let duration = start.elapsed();
-    println!("Sentence splitting elapsed: {:?}", duration);
+    // each document with the page it's from
+    let (docs, pages): (Vec<(Document, usize)>, Vec<Page>) = match &loaded {
+        Some(_) => (Vec::new(), Vec::new()),
+        None => {
+            // get some content to process
+            let start = Instant::now();
+            let cache = open_cache(&opt)?;
+            let fetch = |url: &str| cache.fetch(url, opt.offline);
+            let pages = if opt.crawl {
+                let limits = Crawl {
+                    max_depth: opt.depth,
+                    max_pages: opt.max_pages,
+                };
+                let crawled = crawl(&opt.page, &limits, fetch).expect("Could not crawl from URL");
+                for (url, e) in crawled.failed.iter() {
+                    eprintln!("Could not fetch {}: {}", url, e);
+                }
+                crawled.sources.into_iter().map(|s| s.page).collect()
+            } else {
+                vec![fetch(&opt.page).expect("Could not read from URL or cache")]
+            };
+            let duration = start.elapsed();
+            status!(
+                opt.json,
+                "Fetching {} pages elapsed: {:?}",
+                pages.len(),
+                duration
+            );
+
+            let start = Instant::now();
+            // process the pages into documents, one per sentence
+            let docs = pages
+                .iter()
+                .enumerate()
+                .flat_map(|(source, page)| {
+                    page_documents(page).into_iter().map(move |d| (d, source))
+                })
+                .collect();
+            let duration = start.elapsed();
+            status!(opt.json, "Extracting documents elapsed: {:?}", duration);
+            (docs, pages)
+        }
+    };
+    // saved indexes only keep the text, not the other fields
+    let (sents, sections): (Texts, Vec<&str>) = match &loaded {
+        Some(index) => {
+            status!(
+                opt.json,
+                "Opening saved index elapsed: {:?}",
+                start.elapsed()
+            );
+            if index.scorer() != opt.scorer || index.normalization() != opt.norm {
+                eprintln!(
+                    "Saved index uses scorer {:?} and normalization {:?}",
+                    index.scorer(),
+                    index.normalization()
+                );
+            }
+            (Texts::Saved(index), vec![""; index.num_docs()])
+        }
+        None => (
+            Texts::Extracted(docs.iter().map(|(d, _)| d.body.as_str()).collect()),
+            docs.iter().map(|(d, _)| d.headings.as_str()).collect(),
+        ),
+    };
+    // only worth showing when there's more than one page
+    let sources: Vec<&str> = match pages.len() {
+        0 | 1 => vec![""; sents.len()],
+        _ => docs.iter().map(|(_, p)| pages[*p].url.as_str()).collect(),
+    };
+
+    let duplicates = opt.collapse.then(|| {
+        let start = Instant::now();
+        let dedup = Dedup {
+            threshold: opt.dedup_threshold,
+            ..Dedup::default()
+        };
+        let duplicates = Duplicates::find(&sents.all(), &dedup);
+        status!(
+            opt.json,
+            "Finding {} near-duplicate documents elapsed: {:?}",
+            duplicates.num_duplicates(),
+            start.elapsed()
+        );
+        duplicates
+    });
+    // saved indexes keep docs in order but not their pages, so none for them
+    let doc_pages: Vec<usize> = docs.iter().map(|(_, p)| *p).collect();
+    if opt.context > 0 && loaded.is_some() {
+        return Err("saved indexes don't keep which page docs are from, context needs the pages");
+    }
+    let shown = Shown {
+        sents: &sents,
+        sections: &sections,
+        sources: &sources,
+        pages: &doc_pages,
+        duplicates: duplicates.as_ref(),
+        context: opt.context,
+    };
 
-    let mut sent_lens = Vec::new();
-    let dx = sents.len();
     let en_stemmer = Stemmer::create(Algorithm::English);
 
-    let start = Instant::now();
-    // it's convenient especially for debugging to have a normal inverted index
-    //----------------------------------------(doc  , freq )
-    let mut inverted_idx: HashMap<String, Vec<(usize, usize)>> = HashMap::new();
-    for (idx, sent) in sents.iter().enumerate() {
-        let words = sent.unicode_words().collect::<Vec<&str>>();
-        //-----------------------------term  , freq
-        let mut stemmed_words: HashMap<String, usize> = HashMap::new();
-        for word in words.iter() {
-            let sword = en_stemmer.stem(word.to_lowercase().as_str()).to_string();
-            *stemmed_words.entry(sword.clone()).or_insert(0) += 1;
+    let mut parsed = match Query::parse(&opt.query, opt.op) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("Could not parse query: {}", e);
+            return Err("invalid query");
+        }
+    };
+    if let Some(path) = &opt.synonyms {
+        let synonyms: Synonyms = match fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| text.parse())
+        {
+            Ok(synonyms) => synonyms,
+            Err(e) => {
+                eprintln!("Could not read synonyms from {:?}: {}", path, e);
+                return Err("invalid synonyms");
+            }
+        };
+        parsed = synonyms.expand(&parsed, |word| stem(word, &en_stemmer));
+    }
+    // terms with synonyms are or clauses of their own, which embedding would flatten
+    if opt.boost.is_some() || !parsed.is_flat(opt.op) {
+        if loaded.is_some() {
+            return Err("saved indexes don't keep fields, nested or field queries need the page");
+        }
+        if opt.json {
+            return Err("nested or field queries can't be explained");
         }
-        sent_lens.push(stemmed_words.len());
-        for (sword, freq) in stemmed_words.iter() {
-            inverted_idx
-                .entry(sword.clone())
-                .or_insert(Vec::new())
-                //----(docid,freq)
-                .push((idx, *freq));
+        let start = Instant::now();
+        let mut index = FieldedIndex::new(opt.scorer, opt.norm);
+        for (doc, _) in docs.iter() {
+            index.add(doc);
         }
+        index.refresh();
+        let weights = index.weights();
+        println!(
+            "Generating document {} x fielded term {} matrix elapsed: {:?}",
+            index.num_docs(),
+            index.num_terms(),
+            start.elapsed()
+        );
+        let boosts = opt.boost.unwrap_or_default();
+        println!("\nrank in parallel by fields, for {}", parsed);
+        let (results, stats) = rank_query(&parsed, &index, &weights.view(), &boosts, opt.p);
+        println!("rank_query(): {}", stats);
+        print_results(&results, &shown);
+        return Ok(());
+    }
+
+    if opt.group.is_some() && loaded.is_some() {
+        return Err("saved indexes don't keep which page docs are from, grouping needs the pages");
     }
-    let duration = start.elapsed();
-    println!("Inverting the index elapsed: {:?}", duration);
-    // dbg!(&inverted_idx);
-    let tx = inverted_idx.len();
 
-    let avg_sent_len: f32 = sent_lens.iter().sum::<usize>() as f32 / dx as f32;
-    let k = 1.2;
-    let b = 0.75;
-    let start = Instant::now();
     // for vector boolean retrieval we need full sparse doc x term matrix
     // each row is a document, each column a term
-    let mut doc_term_matrix = Array2::zeros((dx, tx));
-    for (tidx, (_term, postings)) in inverted_idx.iter().enumerate() {
-        for (postidx, freq) in postings.iter() {
-            let tf = *freq as f32 / sent_lens[*postidx] as f32;
-            let idf = (sents.len() as f32 / postings.len() as f32).ln();
-            match opt.scorer {
-                Scorer::TFIDF => {
-                    doc_term_matrix[[*postidx, tidx]] = tf * idf;
-                }
-                Scorer::BM25 => {
-                    let bm25 = idf
-                        * (tf * (k + 1.0) / tf
-                            + k * (1.0 - b + b * sent_lens[*postidx] as f32 / avg_sent_len));
-                    // we hack in a scaling factor so we're beneath 1, otherwise AND breaks
-                    doc_term_matrix[[*postidx, tidx]] = bm25 * 0.01;
-                }
+    let built = match &loaded {
+        Some(_) => None,
+        None => {
+            let start = Instant::now();
+            let sents = sents.all();
+            let index = build_index(&sents, opt.scorer, opt.norm);
+            let duration = start.elapsed();
+            status!(
+                opt.json,
+                "Generating document {} x term {} matrix elapsed: {:?}",
+                index.num_docs(),
+                index.num_terms(),
+                duration
+            );
+            if let Some(dir) = &opt.index {
+                let start = Instant::now();
+                MmapIndex::save(dir, &index, &sents).expect("Could not save index");
+                status!(opt.json, "Saving index elapsed: {:?}", start.elapsed());
             }
+            Some(index)
         }
-    }
-    let duration = start.elapsed();
-    println!(
-        "Generating document {} x term {} matrix elapsed: {:?}",
-        dx, tx, duration
-    );
+    };
+    let doc_term_matrix = match (&loaded, &built) {
+        (Some(index), _) => index.weights(),
+        (_, Some(index)) => index.weights(),
+        _ => unreachable!("index is either loaded or built"),
+    };
     if opt.fixture {
         ndarray_npy::write_npy(
             format!(
@@ -140,23 +436,19 @@ fn main() -> Result<(), &'static str> {
     }
 
     let start = Instant::now();
-    // processing query like sents for term matching
-    let q = opt.query.to_lowercase();
-    let question = q
-        .unicode_words()
-        .map(|w| trim_clean(w))
-        .collect::<HashSet<&str>>();
+    // the parsed terms, already split like sents, so ops aren't matched as terms
+    let question: HashSet<&str> = parsed.terms().into_iter().collect();
     let question_stemmed: HashSet<String> = question
         .iter()
-        .map(|word| en_stemmer.stem(word.to_lowercase().as_str()).to_string())
+        .map(|word| stem(word, &en_stemmer))
         .collect();
     // embedding the query into the term space
-    let mut query: Array1<f32> = Array::zeros(tx);
-    for (tidx, (term, _postings)) in inverted_idx.iter().enumerate() {
-        if question_stemmed.contains(&term.to_string()) {
-            query[tidx] = 1.0;
-        }
-    }
+    let query_terms = question_stemmed.iter().map(|t| t.as_str());
+    let query = match (&loaded, &built) {
+        (Some(index), _) => index.embed(query_terms),
+        (_, Some(index)) => index.embed(query_terms),
+        _ => unreachable!("index is either loaded or built"),
+    };
     assert!(
         query.sum().gt(&0.0),
         "None of the query terms could be found in the document"
@@ -176,7 +468,7 @@ fn main() -> Result<(), &'static str> {
         .expect("wrote out query");
     }
     let duration = start.elapsed();
-    println!("Embedding query elapsed: {:?}", duration);
+    status!(opt.json, "Embedding query elapsed: {:?}", duration);
     //dbg!(&query);
 
     // just testing out obvious outputs
@@ -192,65 +484,495 @@ fn main() -> Result<(), &'static str> {
     //let a = vboo::ranking::rank::and(&query.view(), &doc_term_matrix.row(97));
     //dbg!(o, a);
 
-    if opt.compare {
-        println!("\nrank in parallel using both ops");
+    // near-duplicates but the first of each group are emptied, so they never
+    // take the place of a different result
+    let deduped = duplicates
+        .as_ref()
+        .map(|d| d.without_duplicates(&doc_term_matrix.view()));
+    let doc_term_matrix = match &deduped {
+        Some(deduped) => deduped.view(),
+        None => doc_term_matrix.view(),
+    };
 
-        // these are parallel within and so running them at same time won't be faster now
-        let or_val = rank_parallel(&query.view(), &doc_term_matrix.view(), &Op::OR);
-        let and_val = rank_parallel(&query.view(), &doc_term_matrix.view(), &Op::AND);
+    if let Some(configs) = opt.compare {
+        let current = RankConfig {
+            model: opt.model.unwrap_or(Model::PNorm),
+            op: opt.op,
+            p: opt.p,
+            scorer: opt.scorer,
+            normalization: opt.norm,
+        };
+        let configs = match configs.len() {
+            0 => vec![
+                RankConfig {
+                    op: Op::OR,
+                    ..current
+                },
+                RankConfig {
+                    op: Op::AND,
+                    ..current
+                },
+            ],
+            1 => vec![current, configs[0]],
+            _ => configs,
+        };
 
-        let both = or_val.iter().zip(and_val.iter());
+        // only rebuild the index when scorer or normalization differ
+        let all = sents.all();
+        let mut indexes: Vec<IncrementalIndex> = built.into_iter().collect();
+        let mut rankings: Vec<Vec<RankResult>> = Vec::new();
+        for (idx, config) in configs.iter().enumerate() {
+            let built = |i: &IncrementalIndex| {
+                i.scorer() == config.scorer && i.normalization() == config.normalization
+            };
+            if !indexes.iter().any(built) {
+                indexes.push(build_index(&all, config.scorer, config.normalization));
+            }
+            let index = indexes.iter().find(|i| built(i)).unwrap();
+            // collapsed the same as the single ranking, so each compares k different results
+            let deduped = duplicates
+                .as_ref()
+                .map(|d| d.without_duplicates(&index.weights()));
+            let dtm = match &deduped {
+                Some(deduped) => deduped.view(),
+                None => index.weights(),
+            };
+            let query = index.embed(question_stemmed.iter().map(|t| t.as_str()));
 
-        for (idx, (result_or, result_and)) in both.enumerate() {
-            println!("OR: {} - {:?}", &idx, &result_or);
-            highlight(&sents[result_or.doc_id], &question_stemmed, &en_stemmer);
-            println!("AND: {} - {:?}", &idx, &result_and);
-            highlight(&sents[result_and.doc_id], &question_stemmed, &en_stemmer);
+            println!("\n[{}] rank using {}", idx, config);
+            // these are parallel within and so running them at same time won't be faster now
+            let retriever = config.model.retriever(config.op, config.p);
+            let (results, stats) = retriever.retrieve(&query.view(), &dtm.view());
+            println!("{}", stats);
+            for (rank, result) in results.iter().enumerate() {
+                println!("{} - {:?}", &rank, &result);
+                highlight(sents.get(result.doc_id), &question_stemmed, &en_stemmer);
+            }
+            rankings.push(results);
         }
 
-        let correlation = kendalls::tau_b(
-            &or_val.iter().map(|r| r.doc_id).collect::<Vec<usize>>(),
-            &and_val.iter().map(|r| r.doc_id).collect::<Vec<usize>>(),
-        )
-        .unwrap();
-        println!("rank correlation = {:?}", correlation);
+        for a in 0..configs.len() {
+            for b in (a + 1)..configs.len() {
+                print_comparison(
+                    (a, &configs[a], &rankings[a]),
+                    (b, &configs[b], &rankings[b]),
+                    &sents,
+                );
+            }
+        }
+    } else if let Some(model) = opt.model {
+        println!("\nrank using {}", model);
+        let retriever = model.retriever(opt.op, opt.p);
+        let (results, stats) = retriever.retrieve(&query.view(), &doc_term_matrix.view());
+        println!("{}", stats);
+        print_results(&results, &shown);
     } else {
+        let term = |t: usize| match (&loaded, &built) {
+            (Some(index), _) => index.term(t).to_string(),
+            (_, Some(index)) => index.term(t).to_string(),
+            _ => unreachable!("index is either loaded or built"),
+        };
         // ranking results
+        let (topkv, stats) = rank_parallel(&query.view(), &doc_term_matrix.view(), &opt.op, opt.p);
+        let explanations: Vec<Explanation> = if opt.explain {
+            topkv
+                .iter()
+                .map(|r| {
+                    explain(
+                        &query.view(),
+                        &doc_term_matrix.view(),
+                        r.doc_id,
+                        &opt.op,
+                        opt.p,
+                    )
+                    .with_terms(term)
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+        if opt.json {
+            let json = serde_json::to_string_pretty(&explanations)
+                .map_err(|_| "failed to write explanations as json")?;
+            println!("{}", json);
+            return Ok(());
+        }
         println!("\nrank in parallel using {:?}", opt.op);
-        let topkv = rank_parallel(&query.view(), &doc_term_matrix.view(), &opt.op);
-        for (idx, result) in topkv.iter().enumerate() {
-            println!("{} - {:?} - {}", &idx, &result, &sents[result.doc_id]);
+        println!("rank_parallel(): {}", stats);
+        print_results(&topkv, &shown);
+        for (rank, explanation) in explanations.iter().enumerate() {
+            println!("{} - {}", rank, explanation);
         }
         println!(
             "\nrank in parallel, skimming for top results, using {:?}",
             opt.op
         );
-        let topkv = rank_parallel_skim(&query.view(), &doc_term_matrix.view(), &opt.op);
-        for (idx, result) in topkv.iter().enumerate() {
-            println!("{} - {:?} - {}", &idx, &result, &sents[result.doc_id]);
-        }
+        let (topkv, stats) =
+            rank_parallel_skim(&query.view(), &doc_term_matrix.view(), &opt.op, opt.p);
+        println!("rank_parallel_skim(): {}", stats);
+        print_results(&topkv, &shown);
         println!(
             "\nrank sequentially, skimming for top results, using {:?}",
             opt.op
         );
-        let topk = rank(&query.view(), &doc_term_matrix.view(), &opt.op);
-        let results = topk.into_sorted_vec();
-        for (idx, result) in results.iter().enumerate() {
-            println!("{} - {:?} - {}", &idx, &result, &sents[result.doc_id]);
-            // dbg!(doc_term_matrix.row(result.doc_id));
+        let (results, stats) = rank(&query.view(), &doc_term_matrix.view(), &opt.op, opt.p);
+        println!("rank(): {}", stats);
+        print_results(&results, &shown);
+        println!(
+            "\nrank sequentially, pruning with max scores, using {:?}",
+            opt.op
+        );
+        let (results, stats) = rank_pruned(&query.view(), &doc_term_matrix.view(), &opt.op, opt.p);
+        println!("rank_pruned(): {}", stats);
+        print_results(&results, &shown);
+        println!(
+            "\nrank in parallel, gathering query columns, using {:?}",
+            opt.op
+        );
+        let (results, stats) =
+            rank_gathered(&query.view(), &doc_term_matrix.view(), &opt.op, opt.p);
+        println!("rank_gathered(): {}", stats);
+        print_results(&results, &shown);
+        if opt.op == Op::AND {
+            let prefilter = Prefilter {
+                min_matched: opt.min_matched,
+                min_candidates: opt.min_candidates,
+            };
+            println!("\nrank sequentially, prefiltering by {:?}", prefilter);
+            let (results, stats) =
+                rank_prefiltered(&query.view(), &doc_term_matrix.view(), opt.p, &prefilter);
+            println!("rank_prefiltered(): {}", stats);
+            print_results(&results, &shown);
+        }
+        if opt.expand {
+            let expansion = Expansion {
+                docs: opt.expand_docs,
+                terms: opt.expand_terms,
+                weight: opt.expand_weight,
+            };
+            let (expanded, added) =
+                expand(&query.view(), &doc_term_matrix.view(), &results, &expansion);
+            let added: Vec<String> = added
+                .iter()
+                .map(|(t, w)| format!("{} ({:.3})", term(*t).green(), w))
+                .collect();
+            println!(
+                "\nrank in parallel, expanded with {} from the top {} results, using {:?}",
+                added.join(", "),
+                expansion.docs,
+                opt.op
+            );
+            let (results, stats) =
+                rank_parallel(&expanded.view(), &doc_term_matrix.view(), &opt.op, opt.p);
+            println!("rank_parallel(): {}", stats);
+            print_results(&results, &shown);
+        }
+        if let Some(aggregate) = &opt.group {
+            println!(
+                "\nrank pages in parallel by their sentences' {:?}, using {:?}",
+                aggregate, opt.op
+            );
+            let (grouped, stats) = rank_grouped(
+                &query.view(),
+                &doc_term_matrix.view(),
+                &doc_pages,
+                &opt.op,
+                opt.p,
+                aggregate,
+                opt.group_passages,
+            );
+            println!("rank_grouped(): {}", stats);
+            for (rank, group) in grouped.iter().enumerate() {
+                println!(
+                    "{} - {} ({:.6}, {} sentences matched)",
+                    rank,
+                    pages[group.source].url.bold(),
+                    group.score,
+                    group.matched
+                );
+                for passage in group.passages.iter() {
+                    let mut line =
+                        format!("    {:.6} - {}", passage.score, sents.get(passage.doc_id));
+                    let section = sections[passage.doc_id];
+                    if !section.is_empty() {
+                        line.push_str(&format!(" [{}]", section.italic()));
+                    }
+                    println!("{}", line);
+                }
+            }
+        }
+        if opt.interactive {
+            feedback_session(&query.view(), &doc_term_matrix.view(), &opt, &shown, term);
         }
     }
 
     Ok(())
 }
 
-/// removing whitespace and newlines on both ends
-fn trim_clean(input: &str) -> &str {
-    input
-        .strip_suffix("\r\n")
-        .or(input.strip_suffix("\n"))
-        .unwrap_or(input)
-        .trim()
+/// rank, then take which results are relevant and which aren't from stdin,
+/// moving the query towards the relevant ones with Rocchio and ranking again
+///
+/// Marks add up over the session, each update starting from the original query.
+fn feedback_session<F: Fn(usize) -> String>(
+    query: &ArrayView1<f32>,
+    dt_matrix: &ArrayView2<f32>,
+    opt: &Opt,
+    shown: &Shown,
+    term: F,
+) {
+    let mut relevant: Vec<usize> = Vec::new();
+    let mut nonrelevant: Vec<usize> = Vec::new();
+    let mut updated = query.to_owned();
+    let stdin = io::stdin();
+    loop {
+        let (results, stats) = rank_parallel(&updated.view(), dt_matrix, &opt.op, opt.p);
+        println!("\nrank in parallel with feedback, using {:?}", opt.op);
+        println!("rank_parallel(): {}", stats);
+        print_results(&results, shown);
+        print!("mark results relevant with +rank and not with -rank, like `+0 -2`: ");
+        io::stdout().flush().expect("flushed stdout");
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 || line.trim().is_empty() {
+            return;
+        }
+        let marks: Marks = match line.parse() {
+            Ok(marks) => marks,
+            Err(e) => {
+                eprintln!("{}", e);
+                continue;
+            }
+        };
+        let mut judge = |ranks: Vec<usize>, is_relevant: bool| {
+            for rank in ranks {
+                let doc_id = match results.get(rank) {
+                    Some(result) => result.doc_id,
+                    None => {
+                        eprintln!("No result at rank {}", rank);
+                        continue;
+                    }
+                };
+                // marking a doc again changes your mind about it
+                relevant.retain(|d| *d != doc_id);
+                nonrelevant.retain(|d| *d != doc_id);
+                match is_relevant {
+                    true => relevant.push(doc_id),
+                    false => nonrelevant.push(doc_id),
+                }
+            }
+        };
+        judge(marks.relevant, true);
+        judge(marks.nonrelevant, false);
+
+        updated = rocchio(
+            query,
+            dt_matrix,
+            &relevant,
+            &nonrelevant,
+            &Rocchio::default(),
+        );
+        let mut weighted: Vec<(usize, f32)> = updated
+            .iter()
+            .enumerate()
+            .filter(|(_, w)| **w > 0.0)
+            .map(|(t, w)| (t, *w))
+            .collect();
+        weighted.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        let weighted: Vec<String> = weighted
+            .iter()
+            .take(10)
+            .map(|(t, w)| format!("{} ({:.3})", term(*t), w))
+            .collect();
+        println!(
+            "{} relevant, {} not, query now weighs {}",
+            relevant.len(),
+            nonrelevant.len(),
+            weighted.join(", ")
+        );
+    }
+}
+
+/// documents from the page's html, or from its text when it isn't any,
+/// dropping Project Gutenberg's license and reflowing its lines
+fn page_documents(page: &Page) -> Vec<Document> {
+    if page.is_html() {
+        match extract(&page.body) {
+            Ok(docs) => return docs,
+            Err(e) => eprintln!(
+                "Could not parse html from {}, using its text: {}",
+                page.url, e
+            ),
+        }
+    }
+    if is_gutenberg(&page.body) {
+        return gutenberg::extract(&page.body);
+    }
+    let text = HTML::from_string(page.body.clone(), None)
+        .map(|html| html.text_content)
+        .unwrap_or_else(|_| page.body.clone());
+    Document::from_text(&text.replace('\n', " "))
+}
+
+/// The text of each document, read from a saved index only as it's shown.
+enum Texts<'a> {
+    Extracted(Vec<&'a str>),
+    Saved(&'a MmapIndex),
+}
+
+impl<'a> Texts<'a> {
+    fn get(&self, doc_id: usize) -> &'a str {
+        match self {
+            Texts::Extracted(sents) => sents[doc_id],
+            Texts::Saved(index) => index.text(doc_id),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Texts::Extracted(sents) => sents.len(),
+            Texts::Saved(index) => index.num_docs(),
+        }
+    }
+
+    /// every text, for what needs the whole collection
+    fn all(&self) -> Vec<&'a str> {
+        (0..self.len()).map(|d| self.get(d)).collect()
+    }
+}
+
+/// What's shown of each ranked document besides its text.
+struct Shown<'a> {
+    sents: &'a Texts<'a>,
+    sections: &'a [&'a str],
+    /// page urls, empty with only one page
+    sources: &'a [&'a str],
+    /// which page each document is from
+    pages: &'a [usize],
+    /// near-duplicates results were collapsed by, to count them
+    duplicates: Option<&'a Duplicates>,
+    /// sentences shown either side of each result
+    context: usize,
+}
+
+/// ranked documents with the section and page they're in, and how many
+/// near-duplicates each stands for when they were collapsed
+fn print_results(results: &[RankResult], shown: &Shown) {
+    for (idx, result) in results.iter().enumerate() {
+        let mut line = format!(
+            "{} - {:?} - {}",
+            &idx,
+            &result,
+            shown.sents.get(result.doc_id)
+        );
+        if let Some(count) = shown
+            .duplicates
+            .map(|d| d.count(result.doc_id))
+            .filter(|c| *c > 0)
+        {
+            line.push_str(
+                &format!(" (+{} near-duplicates)", count)
+                    .yellow()
+                    .to_string(),
+            );
+        }
+        let section = shown.sections[result.doc_id];
+        if !section.is_empty() {
+            line.push_str(&format!(" [{}]", section.italic()));
+        }
+        let source = shown.sources[result.doc_id];
+        if !source.is_empty() {
+            line.push_str(&format!(" <{}>", source.dimmed()));
+        }
+        println!("{}", line);
+        if shown.context > 0 {
+            print_context(result.doc_id, shown);
+        }
+    }
+}
+
+/// the sentences around a result from the same page, the result's own in bold
+fn print_context(doc_id: usize, shown: &Shown) {
+    let around: Vec<String> = context(doc_id, shown.pages, shown.context)
+        .map(|d| match d == doc_id {
+            true => shown.sents.get(d).bold().to_string(),
+            false => shown.sents.get(d).dimmed().to_string(),
+        })
+        .collect();
+    println!("    {}", around.join(" "));
+}
+
+/// the cache pages are kept in, only opened once something needs it
+fn open_cache(opt: &Opt) -> Result<Cache, &'static str> {
+    let dir = opt.cache.clone().unwrap_or_else(Cache::default_dir);
+    Cache::open(&dir).map_err(|e| {
+        eprintln!("Could not open cache {:?}: {}", dir, e);
+        "unusable cache"
+    })
+}
+
+/// index every sentence as its own document
+fn build_index(sents: &[&str], scorer: Scorer, norm: Normalization) -> IncrementalIndex {
+    let mut index = IncrementalIndex::new(scorer, norm);
+    for sent in sents.iter() {
+        index.add(sent);
+    }
+    index.refresh();
+    index
+}
+
+/// overlap, rank correlation and which documents moved between two rankings
+fn print_comparison(
+    (ai, a_config, a): (usize, &RankConfig, &[RankResult]),
+    (bi, b_config, b): (usize, &RankConfig, &[RankResult]),
+    sents: &Texts,
+) {
+    let k = a.len().max(b.len());
+    let comparison = compare(a, b, k);
+    println!("\n[{}] {} vs [{}] {}", ai, a_config, bi, b_config);
+    println!("overlap@{} = {:.3}", k, comparison.overlap);
+    println!("rank-biased overlap = {:.3}", comparison.rbo);
+    match comparison.tau {
+        Some(tau) => println!("kendall tau (union) = {:.3}", tau),
+        None => println!("kendall tau (union) = n/a"),
+    }
+
+    println!(
+        "{:<6}{:<24}{:<24}",
+        "rank",
+        format!("[{}]", ai),
+        format!("[{}]", bi)
+    );
+    for rank in 0..k {
+        let cell = |r: Option<&RankResult>| {
+            r.map(|r| format!("{} ({:.4})", r.doc_id, r.score))
+                .unwrap_or_default()
+        };
+        println!(
+            "{:<6}{:<24}{:<24}",
+            rank,
+            cell(a.get(rank)),
+            cell(b.get(rank))
+        );
+    }
+
+    for movement in comparison.movements.iter() {
+        let change = match (movement.from, movement.to, movement.shift()) {
+            (_, _, Some(0)) => continue,
+            (_, _, Some(shift)) if shift > 0 => format!("up {}", shift).green(),
+            (_, _, Some(shift)) => format!("down {}", -shift).yellow(),
+            (None, Some(_), _) => "new".green().bold(),
+            _ => "dropped".red().bold(),
+        };
+        let show = |r: Option<usize>| r.map(|r| r.to_string()).unwrap_or("-".to_string());
+        println!(
+            "{:>10} doc {} ({} -> {}): {}",
+            change,
+            movement.doc_id,
+            show(movement.from),
+            show(movement.to),
+            sents.get(movement.doc_id)
+        );
+    }
 }
 
 /// highlighting match terms
@@ -0,0 +1,183 @@
+use super::{
+    op::Op,
+    rank::{rank, K},
+    rank_result::RankResult,
+    stats::SearchStats,
+    top_k::TopK,
+};
+use ndarray::parallel::prelude::*;
+use ndarray::{Array2, ArrayView2, Axis};
+use std::time::Instant;
+
+/// given embedded queries, one per row, and a document x term matrix, rank
+/// every query by vboo op
+///
+/// For or with p = 1 or 2 and and with p = 1 the sums inside the p-norms are
+/// matrix products of the queries and documents, so the whole batch is
+/// scored by one GEMM through BLAS. Over the n terms of the term space:
+///
+/// - or, p = 1: Σ q·d = Q·Dᵀ
+/// - or, p = 2: Σ (q·d)² = (Q∘Q)·(D∘D)ᵀ
+/// - and, p = 1: Σ (1 - q·d) = n - Q·Dᵀ
+///
+/// And with p = 2 would be n - 2 Q·Dᵀ + (Q∘Q)·(D∘D)ᵀ, but when q·d is near 1
+/// its terms cancel to float noise that the square root then magnifies, so
+/// it and other p fall back to scoring each query with `rank`, queries in
+/// parallel.
+/// Scores can differ from `rank`'s in the last bits, the sums being added in
+/// a different order.
+pub fn rank_batch(
+    queries: &ArrayView2<f32>,
+    dt_matrix: &ArrayView2<f32>,
+    op: &Op,
+    p: f32,
+) -> (Vec<Vec<RankResult>>, SearchStats) {
+    if !(p == 1.0 || (p == 2.0 && *op == Op::OR)) {
+        let start = Instant::now();
+        let ranked: Vec<(Vec<RankResult>, SearchStats)> = queries
+            .axis_iter(Axis(0))
+            .into_par_iter()
+            .map(|query| rank(&query, dt_matrix, op, p))
+            .collect();
+        let mut stats = SearchStats {
+            threads: rayon::current_num_threads(),
+            ..SearchStats::default()
+        };
+        let mut results = Vec::with_capacity(ranked.len());
+        for (ranking, s) in ranked {
+            stats.docs_scanned += s.docs_scanned;
+            stats.docs_matched += s.docs_matched;
+            stats.merging += s.merging;
+            results.push(ranking);
+        }
+        stats.scoring = start.elapsed().saturating_sub(stats.merging);
+        return (results, stats);
+    }
+
+    let start = Instant::now();
+    let n = queries.ncols() as f32;
+    let sums: Array2<f32> = match (op, p == 1.0) {
+        (Op::OR, true) => queries.dot(&dt_matrix.t()),
+        (Op::OR, false) => squared(queries).dot(&squared(dt_matrix).t()),
+        // rounding can take the sum just below 0 when every q·d is 1
+        (Op::AND, _) => queries.dot(&dt_matrix.t()).mapv(|s| (n - s).max(0.0)),
+    };
+
+    let ranked: Vec<TopK> = sums
+        .axis_iter(Axis(0))
+        .into_par_iter()
+        .map(|sums| {
+            let mut topk = TopK::new(K);
+            for (doc_id, sum) in sums.iter().enumerate() {
+                let norm = (sum / n).powf(1.0 / p);
+                let score = match op {
+                    Op::OR => norm,
+                    Op::AND => 1.0 - norm,
+                };
+                topk.offer(RankResult { doc_id, score });
+            }
+            topk
+        })
+        .collect();
+    let scoring = start.elapsed();
+
+    let start = Instant::now();
+    let mut stats = SearchStats {
+        threads: rayon::current_num_threads(),
+        scoring,
+        ..SearchStats::default()
+    };
+    let mut results = Vec::with_capacity(ranked.len());
+    for topk in ranked {
+        stats.docs_scanned += topk.offered();
+        stats.docs_matched += topk.matched();
+        results.push(topk.into_sorted_vec());
+    }
+    stats.merging = start.elapsed();
+    (results, stats)
+}
+
+/// elementwise square, the Hadamard product of a matrix with itself
+fn squared(m: &ArrayView2<f32>) -> Array2<f32> {
+    m.mapv(|w| w * w)
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{array, Array1, Array2};
+    use ndarray_npy::read_npy;
+
+    use crate::ranking::{batch::rank_batch, op::Op, rank::rank, rank_result::RankResult};
+
+    /// same docs in the same order, scores equal up to summation order
+    fn assert_close(expected: &[RankResult], actual: &[RankResult]) {
+        assert_eq!(expected.len(), actual.len(), "{:?} {:?}", expected, actual);
+        for (e, a) in expected.iter().zip(actual.iter()) {
+            assert_eq!(e.doc_id, a.doc_id, "{:?} {:?}", expected, actual);
+            assert!((e.score - a.score).abs() < 1e-5, "{:?} {:?}", e, a);
+        }
+    }
+
+    #[test]
+    fn same_as_rank_per_query() {
+        let dtm: Array2<f32> =
+            read_npy("resources/doc_term_matrix.npy").expect("require test file");
+        let q: Array1<f32> = read_npy("resources/query.npy").expect("require test file");
+        // the fixture query, each of its terms alone, and a weighted one
+        let mut queries = vec![q.clone()];
+        for (term, _) in q.iter().enumerate().filter(|(_, w)| **w > 0.0) {
+            let mut one = Array1::zeros(q.dim());
+            one[term] = 1.0;
+            queries.push(one);
+        }
+        queries.push(q.mapv(|w| w * 0.5));
+        let views: Vec<_> = queries.iter().map(|q| q.view()).collect();
+        let batch = ndarray::stack(ndarray::Axis(0), &views).unwrap();
+
+        for op in [Op::AND, Op::OR] {
+            for p in [1.0, 2.0, 1.5] {
+                let (results, stats) = rank_batch(&batch.view(), &dtm.view(), &op, p);
+                assert_eq!(results.len(), queries.len());
+                assert_eq!(stats.docs_scanned, queries.len() * dtm.nrows());
+                for (query, ranked) in queries.iter().zip(results.iter()) {
+                    let (expected, _) = rank(&query.view(), &dtm.view(), &op, p);
+                    assert_close(&expected, ranked);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn and_near_one_weights() {
+        // every term close to fully held, where n - 2 Q·Dᵀ + (Q∘Q)·(D∘D)ᵀ
+        // would be mostly rounding
+        let dtm = array![
+            [0.9999, 0.9998, 0.9999, 1.0],
+            [1.0, 1.0, 1.0, 1.0],
+            [0.9999, 0.99, 0.9997, 0.9999],
+        ];
+        let queries = array![[1.0, 1.0, 1.0, 1.0], [0.9999, 1.0, 0.9998, 0.9999]];
+        for p in [1.0, 2.0] {
+            let (results, _) = rank_batch(&queries.view(), &dtm.view(), &Op::AND, p);
+            for (query, ranked) in queries.outer_iter().zip(results.iter()) {
+                let (expected, _) = rank(&query, &dtm.view(), &Op::AND, p);
+                assert_close(&expected, ranked);
+                assert!(ranked.iter().all(|r| r.score <= 1.0), "{:?}", ranked);
+            }
+        }
+    }
+
+    #[test]
+    fn empty_query_matches_nothing() {
+        let dtm = array![[0.5, 0.], [0., 0.5]];
+        let queries = array![[0., 0.], [1., 0.]];
+        for op in [Op::AND, Op::OR] {
+            let (results, _) = rank_batch(&queries.view(), &dtm.view(), &op, 2.0);
+            assert!(results[0].is_empty());
+            assert_eq!(
+                results[1].iter().map(|r| r.doc_id).collect::<Vec<_>>(),
+                vec![0]
+            );
+        }
+    }
+}
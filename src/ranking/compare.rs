@@ -0,0 +1,350 @@
+use super::{
+    normalization::Normalization,
+    op::Op,
+    rank::{parse_p, P},
+    rank_result::RankResult,
+    retriever::Model,
+    scorer::Scorer,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    str::FromStr,
+};
+
+/// persistence used for rank-biased overlap, top 10 carry ~86% of the weight
+pub static RBO_P: f32 = 0.9;
+
+/// everything that decides a ranking, so any two can be compared
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RankConfig {
+    pub model: Model,
+    pub op: Op,
+    pub p: f32,
+    pub scorer: Scorer,
+    pub normalization: Normalization,
+}
+
+impl Default for RankConfig {
+    fn default() -> Self {
+        RankConfig {
+            model: Model::PNorm,
+            op: Op::OR,
+            p: P,
+            scorer: Scorer::BM25,
+            normalization: Normalization::None,
+        }
+    }
+}
+
+/// parses `model=pnorm,op=and,p=1.5,scorer=tfidf,norm=max`, any key left out keeps its default
+impl FromStr for RankConfig {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<RankConfig, Self::Err> {
+        let mut config = RankConfig::default();
+        for part in input.split(',').map(|p| p.trim()).filter(|p| !p.is_empty()) {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or(format!("expected key=value, got {}", part))?;
+            match key.trim() {
+                "model" => config.model = value.trim().parse()?,
+                "op" => config.op = value.trim().parse()?,
+                "p" => config.p = parse_p(value)?,
+                "scorer" => config.scorer = value.trim().parse()?,
+                "norm" | "normalization" => config.normalization = value.trim().parse()?,
+                _ => return Err(format!("unsupported config key {}", key)),
+            }
+        }
+        config.model.check(config.scorer, config.normalization)?;
+        Ok(config)
+    }
+}
+
+impl fmt::Display for RankConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "model={},op={},p={},scorer={},norm={}",
+            self.model,
+            format!("{:?}", self.op).to_lowercase(),
+            self.p,
+            format!("{:?}", self.scorer).to_lowercase(),
+            format!("{:?}", self.normalization).to_lowercase()
+        )
+    }
+}
+
+/// where a document sat in each ranking, None if it wasn't there
+#[derive(Debug, PartialEq)]
+pub struct Movement {
+    pub doc_id: usize,
+    pub from: Option<usize>,
+    pub to: Option<usize>,
+}
+
+impl Movement {
+    /// positive when the document moved up, None if it entered or dropped out
+    pub fn shift(&self) -> Option<isize> {
+        match (self.from, self.to) {
+            (Some(from), Some(to)) => Some(from as isize - to as isize),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Comparison {
+    pub overlap: f32,
+    pub rbo: f32,
+    /// None when there are too few documents to correlate
+    pub tau: Option<f64>,
+    pub movements: Vec<Movement>,
+}
+
+/// compare two rankings, each already sorted best first
+pub fn compare(a: &[RankResult], b: &[RankResult], k: usize) -> Comparison {
+    Comparison {
+        overlap: overlap_at_k(a, b, k),
+        rbo: rank_biased_overlap(a, b, RBO_P),
+        tau: kendall_tau_union(a, b),
+        movements: movements(a, b),
+    }
+}
+
+/// fraction of the top k of one ranking found in the top k of the other
+pub fn overlap_at_k(a: &[RankResult], b: &[RankResult], k: usize) -> f32 {
+    if k == 0 {
+        return 0.0;
+    }
+    let top_a: HashSet<usize> = a.iter().take(k).map(|r| r.doc_id).collect();
+    let shared = b
+        .iter()
+        .take(k)
+        .filter(|r| top_a.contains(&r.doc_id))
+        .count();
+    shared as f32 / k as f32
+}
+
+/// extrapolated rank-biased overlap for uneven lists (Webber et al. 2010, eq. 32)
+///
+/// 1 for identical rankings and 0 for disjoint ones, weighting the top more heavily
+pub fn rank_biased_overlap(a: &[RankResult], b: &[RankResult], p: f32) -> f32 {
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let (l, s) = (long.len(), short.len());
+    if l == 0 {
+        return 1.0;
+    }
+    if s == 0 {
+        return 0.0;
+    }
+    let p = p as f64;
+
+    // overlap[d] is the size of the intersection at depth d
+    let mut overlap = vec![0usize; l + 1];
+    let mut seen_long = HashSet::new();
+    let mut seen_short = HashSet::new();
+    let mut shared = 0;
+    for d in 1..=l {
+        let ld = long[d - 1].doc_id;
+        if d <= s {
+            let sd = short[d - 1].doc_id;
+            if ld == sd {
+                shared += 1;
+            } else {
+                if seen_short.contains(&ld) {
+                    shared += 1;
+                }
+                if seen_long.contains(&sd) {
+                    shared += 1;
+                }
+            }
+            seen_short.insert(sd);
+        } else if seen_short.contains(&ld) {
+            shared += 1;
+        }
+        seen_long.insert(ld);
+        overlap[d] = shared;
+    }
+
+    let (x_l, x_s) = (overlap[l] as f64, overlap[s] as f64);
+    let mut sum = 0.0;
+    for (d, x_d) in overlap.iter().enumerate().skip(1) {
+        let d_f = d as f64;
+        sum += *x_d as f64 / d_f * p.powi(d as i32);
+        if d > s {
+            sum += x_s * (d - s) as f64 / (s as f64 * d_f) * p.powi(d as i32);
+        }
+    }
+    let tail = ((x_l - x_s) / l as f64 + x_s / s as f64) * p.powi(l as i32);
+    ((1.0 - p) / p * sum + tail) as f32
+}
+
+/// Kendall tau-b over the union of both rankings
+///
+/// a document missing from a ranking is treated as tied for last place in it
+pub fn kendall_tau_union(a: &[RankResult], b: &[RankResult]) -> Option<f64> {
+    let rank_a = positions(a);
+    let rank_b = positions(b);
+    let union = union_ids(a, b);
+    let ra: Vec<usize> = union
+        .iter()
+        .map(|id| *rank_a.get(id).unwrap_or(&a.len()))
+        .collect();
+    let rb: Vec<usize> = union
+        .iter()
+        .map(|id| *rank_b.get(id).unwrap_or(&b.len()))
+        .collect();
+    kendalls::tau_b(&ra, &rb).ok().filter(|t| !t.is_nan())
+}
+
+/// every document in either ranking, in the order of the first then new arrivals in the second
+pub fn movements(a: &[RankResult], b: &[RankResult]) -> Vec<Movement> {
+    let rank_a = positions(a);
+    let rank_b = positions(b);
+    union_ids(a, b)
+        .into_iter()
+        .map(|doc_id| Movement {
+            doc_id,
+            from: rank_a.get(&doc_id).copied(),
+            to: rank_b.get(&doc_id).copied(),
+        })
+        .collect()
+}
+
+fn positions(results: &[RankResult]) -> HashMap<usize, usize> {
+    results
+        .iter()
+        .enumerate()
+        .map(|(idx, r)| (r.doc_id, idx))
+        .collect()
+}
+
+fn union_ids(a: &[RankResult], b: &[RankResult]) -> Vec<usize> {
+    let mut seen = HashSet::new();
+    a.iter()
+        .chain(b.iter())
+        .map(|r| r.doc_id)
+        .filter(|id| seen.insert(*id))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ranking::{
+        compare::{
+            kendall_tau_union, movements, overlap_at_k, rank_biased_overlap, Movement, RankConfig,
+        },
+        normalization::Normalization,
+        op::Op,
+        rank_result::RankResult,
+        retriever::Model,
+        scorer::Scorer,
+    };
+
+    fn ranked(ids: &[usize]) -> Vec<RankResult> {
+        ids.iter()
+            .enumerate()
+            .map(|(idx, doc_id)| RankResult {
+                doc_id: *doc_id,
+                score: 1.0 / (idx + 1) as f32,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parse_config() {
+        let c: RankConfig = "op=and, p=1.5,scorer=tfidf,norm=max,model=cosine"
+            .parse()
+            .unwrap();
+        assert_eq!(c.model, Model::Cosine);
+        assert_eq!(c.op, Op::AND);
+        assert_eq!(c.p, 1.5);
+        assert_eq!(c.scorer, Scorer::TFIDF);
+        assert_eq!(c.normalization, Normalization::Max);
+        assert_eq!(c, c.to_string().parse().unwrap());
+        assert_eq!(RankConfig::default(), "".parse().unwrap());
+        assert!("op=xor".parse::<RankConfig>().is_err());
+        assert!("and".parse::<RankConfig>().is_err());
+        assert!("model=lsi".parse::<RankConfig>().is_err());
+        assert!("p=0".parse::<RankConfig>().is_err());
+        assert!("p=-1".parse::<RankConfig>().is_err());
+        assert!("p=NaN".parse::<RankConfig>().is_err());
+        assert!("p=inf".parse::<RankConfig>().is_err());
+        assert!("model=bm25".parse::<RankConfig>().is_ok());
+        assert!("model=bm25,scorer=tfidf".parse::<RankConfig>().is_err());
+        assert!("model=bm25,norm=max".parse::<RankConfig>().is_err());
+    }
+
+    #[test]
+    fn identical_rankings() {
+        let a = ranked(&[1, 2, 3, 4]);
+        assert_eq!(1.0, overlap_at_k(&a, &a, 4));
+        assert!((rank_biased_overlap(&a, &a, 0.9) - 1.0).abs() < 1e-6);
+        assert_eq!(Some(1.0), kendall_tau_union(&a, &a));
+    }
+
+    #[test]
+    fn disjoint_rankings() {
+        let a = ranked(&[1, 2, 3]);
+        let b = ranked(&[4, 5, 6]);
+        assert_eq!(0.0, overlap_at_k(&a, &b, 3));
+        assert_eq!(0.0, rank_biased_overlap(&a, &b, 0.9));
+        assert!(kendall_tau_union(&a, &b).unwrap() < 0.0);
+    }
+
+    #[test]
+    fn reversed_rankings() {
+        let a = ranked(&[1, 2, 3, 4]);
+        let b = ranked(&[4, 3, 2, 1]);
+        assert_eq!(1.0, overlap_at_k(&a, &b, 4));
+        assert_eq!(Some(-1.0), kendall_tau_union(&a, &b));
+        let rbo = rank_biased_overlap(&a, &b, 0.9);
+        assert!(rbo > 0.0 && rbo < 1.0);
+    }
+
+    #[test]
+    fn uneven_rankings() {
+        let a = ranked(&[1, 2, 3, 4]);
+        let b = ranked(&[1, 2]);
+        assert_eq!(0.5, overlap_at_k(&a, &b, 4));
+        // a prefix agrees with everything it has seen
+        assert!((rank_biased_overlap(&a, &b, 0.9) - 1.0).abs() < 1e-6);
+        assert_eq!(0.0, rank_biased_overlap(&a, &[], 0.9));
+    }
+
+    #[test]
+    fn movement_between_rankings() {
+        let a = ranked(&[1, 2, 3]);
+        let b = ranked(&[3, 1, 4]);
+        let m = movements(&a, &b);
+        assert_eq!(
+            m,
+            vec![
+                Movement {
+                    doc_id: 1,
+                    from: Some(0),
+                    to: Some(1)
+                },
+                Movement {
+                    doc_id: 2,
+                    from: Some(1),
+                    to: None
+                },
+                Movement {
+                    doc_id: 3,
+                    from: Some(2),
+                    to: Some(0)
+                },
+                Movement {
+                    doc_id: 4,
+                    from: None,
+                    to: Some(2)
+                },
+            ]
+        );
+        assert_eq!(Some(-1), m[0].shift());
+        assert_eq!(Some(2), m[2].shift());
+        assert_eq!(None, m[3].shift());
+    }
+}
@@ -0,0 +1,197 @@
+use super::op::Op;
+use ndarray::{ArrayView1, ArrayView2};
+use serde::Serialize;
+use std::fmt;
+
+/// How one query term adds to a document's p-norm.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TermContribution {
+    pub term_id: usize,
+    /// the term itself, when the caller named it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub term: Option<String>,
+    pub query_weight: f32,
+    pub doc_weight: f32,
+    /// (q·d)^p for or, (1 - q·d)^p for and
+    pub contribution: f32,
+}
+
+/// Why a document scored what it did, term by term.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Explanation {
+    pub doc_id: usize,
+    pub op: Op,
+    pub p: f32,
+    /// what the sum is divided by, the size of the term space
+    pub normalizer: usize,
+    /// the query's terms, those with weight, in term id order
+    pub terms: Vec<TermContribution>,
+    /// what every other term of the term space adds together, 1 each for
+    /// and as (1 - 0)^p = 1, nothing for or
+    pub rest: f32,
+    pub sum: f32,
+    pub score: f32,
+}
+
+/// each query term's part in a document's p-norm score
+///
+/// The score is worked out again from the contributions, so it can differ
+/// from `rank`'s in the last bits, the sum being added in another order.
+pub fn explain(
+    query: &ArrayView1<f32>,
+    dt_matrix: &ArrayView2<f32>,
+    doc_id: usize,
+    op: &Op,
+    p: f32,
+) -> Explanation {
+    let doc = dt_matrix.row(doc_id);
+    let n = query.dim();
+    let terms: Vec<TermContribution> = query
+        .iter()
+        .zip(doc.iter())
+        .enumerate()
+        .filter(|(_, (q, _))| **q != 0.0)
+        .map(|(term_id, (q, d))| TermContribution {
+            term_id,
+            term: None,
+            query_weight: *q,
+            doc_weight: *d,
+            contribution: match op {
+                Op::OR => (q * d).powf(p),
+                Op::AND => (1.0 - q * d).powf(p),
+            },
+        })
+        .collect();
+    let rest = match op {
+        Op::OR => 0.0,
+        Op::AND => (n - terms.len()) as f32,
+    };
+    let sum = terms.iter().map(|t| t.contribution).sum::<f32>() + rest;
+    let norm = (sum / n as f32).powf(1.0 / p);
+    Explanation {
+        doc_id,
+        op: *op,
+        p,
+        normalizer: n,
+        terms,
+        rest,
+        sum,
+        score: match op {
+            Op::OR => norm,
+            Op::AND => 1.0 - norm,
+        },
+    }
+}
+
+impl Explanation {
+    /// name the terms, for showing
+    pub fn with_terms<F: Fn(usize) -> String>(mut self, name: F) -> Explanation {
+        for term in self.terms.iter_mut() {
+            term.term = Some(name(term.term_id));
+        }
+        self
+    }
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (contribution, root) = match self.op {
+            Op::OR => ("(q·d)^p", ""),
+            Op::AND => ("(1-q·d)^p", "1 - "),
+        };
+        writeln!(
+            f,
+            "doc {} by {:?} with p = {}",
+            self.doc_id, self.op, self.p
+        )?;
+        writeln!(
+            f,
+            "  {:<16} {:>8} {:>8} {:>10}",
+            "term", "query", "doc", contribution
+        )?;
+        for t in self.terms.iter() {
+            let name = t.term.clone().unwrap_or_else(|| t.term_id.to_string());
+            writeln!(
+                f,
+                "  {:<16} {:>8.4} {:>8.4} {:>10.6}",
+                name, t.query_weight, t.doc_weight, t.contribution
+            )?;
+        }
+        if self.rest != 0.0 {
+            writeln!(f, "  {:<16} {:>28}", "other terms", self.rest)?;
+        }
+        write!(
+            f,
+            "  score = {}({:.6} / {})^(1/{}) = {:.6}",
+            root, self.sum, self.normalizer, self.p, self.score
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{array, Array1, Array2};
+    use ndarray_npy::read_npy;
+
+    use crate::ranking::{explain::explain, op::Op, rank::score};
+
+    #[test]
+    fn adds_up_to_score() {
+        let dtm: Array2<f32> =
+            read_npy("resources/doc_term_matrix.npy").expect("require test file");
+        let q: Array1<f32> = read_npy("resources/query.npy").expect("require test file");
+        for op in [Op::AND, Op::OR] {
+            for p in [1.0, 2.0, 3.5] {
+                for doc_id in [0, 97, dtm.nrows() - 1] {
+                    let explanation = explain(&q.view(), &dtm.view(), doc_id, &op, p);
+                    let expected = score(&q.view(), &dtm.row(doc_id), &op, p);
+                    assert!(
+                        (explanation.score - expected).abs() < 1e-5,
+                        "{} != {}",
+                        explanation,
+                        expected
+                    );
+                    assert_eq!(explanation.normalizer, q.dim());
+                    assert_eq!(
+                        explanation.terms.len(),
+                        q.iter().filter(|w| **w > 0.0).count()
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn contributions_per_term() {
+        let dtm = array![[0.5, 0.0, 1.0], [0.0, 0.0, 0.0]];
+        let query = array![1.0, 1.0, 0.0];
+        let or = explain(&query.view(), &dtm.view(), 0, &Op::OR, 2.0);
+        let contributions: Vec<(usize, f32)> = or
+            .terms
+            .iter()
+            .map(|t| (t.term_id, t.contribution))
+            .collect();
+        assert_eq!(contributions, vec![(0, 0.25), (1, 0.0)]);
+        assert_eq!((or.rest, or.sum), (0.0, 0.25));
+        assert_eq!(or.score, (0.25f32 / 3.0).sqrt());
+
+        let and = explain(&query.view(), &dtm.view(), 0, &Op::AND, 2.0);
+        let contributions: Vec<f32> = and.terms.iter().map(|t| t.contribution).collect();
+        assert_eq!(contributions, vec![0.25, 1.0]);
+        // the doc's weight for the third term doesn't count, the query has none
+        assert_eq!((and.rest, and.sum), (1.0, 2.25));
+        assert_eq!(and.score, 1.0 - (2.25f32 / 3.0).sqrt());
+    }
+
+    #[test]
+    fn serializes_to_json() {
+        let dtm = array![[0.5, 0.0]];
+        let query = array![1.0, 0.0];
+        let explanation = explain(&query.view(), &dtm.view(), 0, &Op::OR, 1.0)
+            .with_terms(|t| ["rust", "crab"][t].to_string());
+        assert_eq!(
+            serde_json::to_string(&explanation).unwrap(),
+            r#"{"doc_id":0,"op":"or","p":1.0,"normalizer":2,"terms":[{"term_id":0,"term":"rust","query_weight":1.0,"doc_weight":0.5,"contribution":0.5}],"rest":0.0,"sum":0.5,"score":0.25}"#
+        );
+    }
+}
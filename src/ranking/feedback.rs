@@ -0,0 +1,242 @@
+use super::rank_result::RankResult;
+use ndarray::{Array1, ArrayView1, ArrayView2, Axis};
+use std::str::FromStr;
+
+/// Weights of the query and of the relevant and non-relevant documents'
+/// centroids in Rocchio's query update.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rocchio {
+    pub alpha: f32,
+    pub beta: f32,
+    pub gamma: f32,
+}
+
+impl Default for Rocchio {
+    fn default() -> Self {
+        Rocchio {
+            alpha: 1.0,
+            beta: 0.75,
+            gamma: 0.15,
+        }
+    }
+}
+
+/// Results marked by their rank, as in `+0 +2 -1`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Marks {
+    pub relevant: Vec<usize>,
+    pub nonrelevant: Vec<usize>,
+}
+
+impl FromStr for Marks {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Marks, Self::Err> {
+        let mut marks = Marks::default();
+        for mark in input.split(|c: char| c.is_whitespace() || c == ',') {
+            let (list, rank) = match (mark.strip_prefix('+'), mark.strip_prefix('-')) {
+                _ if mark.is_empty() => continue,
+                (Some(rank), _) => (&mut marks.relevant, rank),
+                (_, Some(rank)) => (&mut marks.nonrelevant, rank),
+                _ => return Err(format!("expected +rank or -rank, got {}", mark)),
+            };
+            list.push(
+                rank.parse()
+                    .map_err(|_| format!("rank must be a number, got {}", mark))?,
+            );
+        }
+        Ok(marks)
+    }
+}
+
+/// Rocchio's relevance feedback, moving the query towards the relevant
+/// documents and away from the non-relevant ones
+///
+/// q' = α q + β mean(relevant) - γ mean(non-relevant), clamped to [0, 1]
+/// as p-norm weights are. Either set of documents can be empty.
+pub fn rocchio(
+    query: &ArrayView1<f32>,
+    dt_matrix: &ArrayView2<f32>,
+    relevant: &[usize],
+    nonrelevant: &[usize],
+    params: &Rocchio,
+) -> Array1<f32> {
+    let mut updated = query.mapv(|w| params.alpha * w);
+    if let Some(centroid) = centroid(dt_matrix, relevant) {
+        updated.scaled_add(params.beta, &centroid);
+    }
+    if let Some(centroid) = centroid(dt_matrix, nonrelevant) {
+        updated.scaled_add(-params.gamma, &centroid);
+    }
+    updated.mapv_inplace(|w| w.clamp(0.0, 1.0));
+    updated
+}
+
+/// How a query is expanded from its own top results.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Expansion {
+    /// top results taken as relevant
+    pub docs: usize,
+    /// new terms added to the query at most
+    pub terms: usize,
+    /// weight of the strongest new term, the others scaled down from it
+    pub weight: f32,
+}
+
+impl Default for Expansion {
+    fn default() -> Self {
+        Expansion {
+            docs: 5,
+            terms: 5,
+            weight: 0.5,
+        }
+    }
+}
+
+/// pseudo-relevance feedback, assuming the top ranked documents are relevant
+/// and adding the terms weighing the most across them to the query
+///
+/// Like Rocchio with only relevant documents, but the query's own weights are
+/// left as they are and only the strongest new terms are added, weighted by
+/// their mean weight in the top documents relative to the strongest one's,
+/// up to `weight`. Returns the expanded query and the (term, weight) added,
+/// strongest first.
+pub fn expand(
+    query: &ArrayView1<f32>,
+    dt_matrix: &ArrayView2<f32>,
+    ranked: &[RankResult],
+    expansion: &Expansion,
+) -> (Array1<f32>, Vec<(usize, f32)>) {
+    let top: Vec<usize> = ranked
+        .iter()
+        .take(expansion.docs)
+        .map(|r| r.doc_id)
+        .collect();
+    let mut expanded = query.to_owned();
+    let centroid = match centroid(dt_matrix, &top) {
+        Some(centroid) => centroid,
+        None => return (expanded, Vec::new()),
+    };
+
+    let mut candidates: Vec<(usize, f32)> = centroid
+        .iter()
+        .enumerate()
+        .filter(|(term, w)| query[*term] == 0.0 && **w > 0.0)
+        .map(|(term, w)| (term, *w))
+        .collect();
+    // strongest first, ties by term id so expansion is deterministic
+    candidates.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+    candidates.truncate(expansion.terms);
+
+    let strongest = candidates.first().map_or(1.0, |(_, w)| *w);
+    let added: Vec<(usize, f32)> = candidates
+        .into_iter()
+        .map(|(term, w)| (term, expansion.weight * w / strongest))
+        .collect();
+    for (term, w) in added.iter() {
+        expanded[*term] = *w;
+    }
+    (expanded, added)
+}
+
+/// mean of the documents' rows
+fn centroid(dt_matrix: &ArrayView2<f32>, docs: &[usize]) -> Option<Array1<f32>> {
+    if docs.is_empty() {
+        return None;
+    }
+    dt_matrix.select(Axis(0), docs).mean_axis(Axis(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::array;
+
+    use crate::ranking::{
+        feedback::{expand, rocchio, Expansion, Marks, Rocchio},
+        op::Op,
+        rank::rank,
+    };
+
+    #[test]
+    fn adds_terms_of_top_docs() {
+        // terms: spanish, square, plaza, fountain, weather
+        let dtm = array![
+            [0.8, 0.6, 0.4, 0.0, 0.0],
+            [0.7, 0.0, 0.6, 0.2, 0.0],
+            [0.0, 0.0, 0.9, 0.0, 0.0],
+            [0.0, 0.0, 0.0, 0.0, 0.9],
+        ];
+        let query = array![1.0, 1.0, 0.0, 0.0, 0.0];
+        let (ranked, _) = rank(&query.view(), &dtm.view(), &Op::OR, 2.0);
+        assert_eq!(
+            ranked.iter().map(|r| r.doc_id).collect::<Vec<_>>(),
+            vec![0, 1]
+        );
+
+        let expansion = Expansion {
+            docs: 2,
+            terms: 5,
+            weight: 0.5,
+        };
+        let (expanded, added) = expand(&query.view(), &dtm.view(), &ranked, &expansion);
+        // plaza has mean 0.5 and fountain 0.1, weather isn't in the top docs
+        assert_eq!(added, vec![(2, 0.5), (3, 0.1)]);
+        assert_eq!(expanded, array![1.0, 1.0, 0.5, 0.1, 0.0]);
+
+        // the doc only about plaza is found now
+        let (reranked, _) = rank(&expanded.view(), &dtm.view(), &Op::OR, 2.0);
+        assert!(reranked.iter().any(|r| r.doc_id == 2));
+        assert!(!reranked.iter().any(|r| r.doc_id == 3));
+
+        let one = Expansion {
+            terms: 1,
+            ..expansion
+        };
+        assert_eq!(
+            expand(&query.view(), &dtm.view(), &ranked, &one).1,
+            vec![(2, 0.5)]
+        );
+    }
+
+    #[test]
+    fn moves_query_towards_relevant() {
+        let dtm = array![[0.8, 0.4, 0.0], [0.0, 0.4, 0.8], [0.0, 0.0, 0.4]];
+        let query = array![0.0, 1.0, 0.0];
+        let params = Rocchio {
+            alpha: 1.0,
+            beta: 0.5,
+            gamma: 0.25,
+        };
+        let updated = rocchio(&query.view(), &dtm.view(), &[0], &[1, 2], &params);
+        // 1 + 0.5 * 0.4 - 0.25 * 0.2 is over 1, 0 - 0.25 * 0.6 under 0
+        assert_eq!(updated, array![0.4, 1.0, 0.0]);
+        let (ranked, _) = rank(&updated.view(), &dtm.view(), &Op::OR, 2.0);
+        assert_eq!(ranked[0].doc_id, 0);
+
+        assert_eq!(
+            rocchio(&query.view(), &dtm.view(), &[], &[], &params),
+            query
+        );
+    }
+
+    #[test]
+    fn parses_marks() {
+        let marks: Marks = "+0 -2, +3".parse().unwrap();
+        assert_eq!(marks.relevant, vec![0, 3]);
+        assert_eq!(marks.nonrelevant, vec![2]);
+        assert_eq!("  ".parse::<Marks>(), Ok(Marks::default()));
+        assert!("3".parse::<Marks>().is_err());
+        assert!("+x".parse::<Marks>().is_err());
+        assert!("+".parse::<Marks>().is_err());
+        assert!("é1".parse::<Marks>().is_err());
+    }
+
+    #[test]
+    fn nothing_to_expand_from() {
+        let dtm = array![[0.5, 0.0], [0.0, 0.5]];
+        let query = array![1.0, 0.0];
+        let (expanded, added) = expand(&query.view(), &dtm.view(), &[], &Expansion::default());
+        assert_eq!(expanded, query);
+        assert!(added.is_empty());
+    }
+}
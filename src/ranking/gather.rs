@@ -0,0 +1,128 @@
+use super::{
+    kernel,
+    op::Op,
+    rank::{finish, K},
+    rank_result::RankResult,
+    stats::SearchStats,
+    top_k::TopK,
+};
+use ndarray::parallel::prelude::*;
+use ndarray::{Array2, ArrayView1, ArrayView2, Axis};
+use std::time::Instant;
+
+/// given an embedded query and a document x term matrix, rank by vboo op
+/// over only the query's columns
+///
+/// Terms the query doesn't have add 0 to or and 1 to and whatever the
+/// document's weight, so the columns of the query terms are gathered into a
+/// contiguous docs x query terms block and scored as an n dimensional term
+/// space they only cover part of. That's two passes rather than one, the
+/// first gathering the columns out of the matrix and the second scoring the
+/// block, but both cost docs x query terms rather than docs x vocabulary.
+/// Scores can differ from `rank`'s in the last bits, the uncovered terms being
+/// added in one go.
+pub fn rank_gathered(
+    query: &ArrayView1<f32>,
+    dt_matrix: &ArrayView2<f32>,
+    op: &Op,
+    p: f32,
+) -> (Vec<RankResult>, SearchStats) {
+    let start = Instant::now();
+    let n = query.dim();
+    let cols: Vec<usize> = query
+        .iter()
+        .enumerate()
+        .filter(|(_, q)| **q != 0.0)
+        .map(|(term, _)| term)
+        .collect();
+    let q = query.select(Axis(0), &cols);
+    let q = q.as_slice().expect("select is contiguous");
+    // row by row, select along columns would copy them column major
+    let block = Array2::from_shape_fn((dt_matrix.nrows(), cols.len()), |(doc_id, i)| {
+        dt_matrix[[doc_id, cols[i]]]
+    });
+
+    let topk = block
+        .axis_iter(Axis(0))
+        .into_par_iter()
+        .enumerate()
+        .map(|(doc_id, doc)| {
+            let doc = doc.to_slice().expect("gathered row major");
+            let score = match op {
+                Op::AND => kernel::and_n(q, doc, p, n),
+                Op::OR => kernel::or_n(q, doc, p, n),
+            };
+            RankResult { doc_id, score }
+        })
+        .fold(
+            || TopK::new(K),
+            |mut topk, rr| {
+                topk.offer(rr);
+                topk
+            },
+        )
+        .reduce(|| TopK::new(K), TopK::merge);
+    let scoring = start.elapsed();
+
+    let start = Instant::now();
+    finish(topk, rayon::current_num_threads(), scoring, start)
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{Array1, Array2};
+    use ndarray_npy::read_npy;
+    use proptest::prelude::*;
+
+    use crate::ranking::{
+        gather::rank_gathered,
+        op::Op,
+        rank::{rank, score},
+    };
+
+    fn weight() -> impl Strategy<Value = f32> {
+        // plenty of exact zeros and repeats so ties and empty docs come up
+        prop_oneof![Just(0.0f32), Just(0.5f32), 0.0f32..1.0]
+    }
+
+    proptest! {
+        #[test]
+        fn same_as_rank(
+            (dtm, query) in (1usize..60, 1usize..30).prop_flat_map(|(docs, terms)| (
+                prop::collection::vec(weight(), docs * terms)
+                    .prop_map(move |w| Array2::from_shape_vec((docs, terms), w).unwrap()),
+                prop::collection::vec(prop_oneof![Just(0.0f32), Just(1.0f32)], terms)
+                    .prop_map(Array1::from),
+            )),
+            op in prop_oneof![Just(Op::AND), Just(Op::OR)],
+            p in prop_oneof![Just(1.0f32), Just(2.0f32), 1.0f32..4.0],
+        ) {
+            let (expected, expected_stats) = rank(&query.view(), &dtm.view(), &op, p);
+            let (gathered, stats) = rank_gathered(&query.view(), &dtm.view(), &op, p);
+            prop_assert_eq!(expected.len(), gathered.len());
+            // near ties can swap, so every doc has to score what the expected one at its rank does
+            let close = |a: f32, b: f32| (a - b).abs() <= 1e-5 * (1.0 + a.abs());
+            for (e, g) in expected.iter().zip(gathered.iter()) {
+                let full = score(&query.view(), &dtm.row(g.doc_id), &op, p);
+                prop_assert!(close(e.score, g.score), "{:?} {:?}", e, g);
+                prop_assert!(close(e.score, full), "{:?} {:?}", e, g);
+            }
+            prop_assert_eq!(expected_stats.docs_scanned, stats.docs_scanned);
+        }
+    }
+
+    #[test]
+    fn same_as_rank_on_fixture() {
+        let dtm: Array2<f32> =
+            read_npy("resources/doc_term_matrix.npy").expect("require test file");
+        let q: Array1<f32> = read_npy("resources/query.npy").expect("require test file");
+        for op in [Op::AND, Op::OR] {
+            let (expected, _) = rank(&q.view(), &dtm.view(), &op, 2.0);
+            let (gathered, _) = rank_gathered(&q.view(), &dtm.view(), &op, 2.0);
+            let ids = |r: &[crate::ranking::rank_result::RankResult]| {
+                r.iter().map(|r| r.doc_id).collect::<Vec<usize>>()
+            };
+            assert_eq!(ids(&expected), ids(&gathered));
+        }
+    }
+}
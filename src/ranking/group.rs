@@ -0,0 +1,220 @@
+use super::{op::Op, rank::score, rank::K, rank_result::RankResult, stats::SearchStats};
+use ndarray::parallel::prelude::*;
+use ndarray::{ArrayView1, ArrayView2, Axis};
+use std::{collections::HashMap, str::FromStr, time::Instant};
+
+/// How a source's passage scores add up to its own.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aggregate {
+    /// its best passage's
+    Max,
+    /// all its matching passages', favouring sources with many
+    Sum,
+    /// its best n passages', missing ones counting as 0
+    Mean(usize),
+}
+
+impl FromStr for Aggregate {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Aggregate, Self::Err> {
+        match input.split_once(':') {
+            None if input == "max" => Ok(Aggregate::Max),
+            None if input == "sum" => Ok(Aggregate::Sum),
+            None if input == "mean" => Ok(Aggregate::Mean(3)),
+            Some(("mean", n)) => match n.parse() {
+                Ok(n) if n > 0 => Ok(Aggregate::Mean(n)),
+                _ => Err(format!(
+                    "mean needs a positive number of passages, got {}",
+                    n
+                )),
+            },
+            _ => Err(format!(
+                "unsupported aggregate {}, expected max, sum, mean or mean:n",
+                input
+            )),
+        }
+    }
+}
+
+impl Aggregate {
+    /// the source's score from its passages', best first
+    pub fn score(&self, passages: &[RankResult]) -> f32 {
+        match self {
+            Aggregate::Max => passages.first().map_or(0.0, |r| r.score),
+            Aggregate::Sum => passages.iter().map(|r| r.score).sum(),
+            Aggregate::Mean(n) => {
+                passages.iter().take(*n).map(|r| r.score).sum::<f32>() / *n as f32
+            }
+        }
+    }
+}
+
+/// A source document ranked by its passages, with the best of them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceResult {
+    pub source: usize,
+    pub score: f32,
+    /// its top passages, best first
+    pub passages: Vec<RankResult>,
+    /// its passages matching the query at all
+    pub matched: usize,
+}
+
+/// given an embedded query, a passage x term matrix and the source each
+/// passage is from, rank the sources by vboo op over their passages
+///
+/// Every passage is scored, not only the top k, so sums and means see all of
+/// a source's matches. Returns the top k sources, each with up to `passages`
+/// of its best passages.
+pub fn rank_grouped(
+    query: &ArrayView1<f32>,
+    dt_matrix: &ArrayView2<f32>,
+    sources: &[usize],
+    op: &Op,
+    p: f32,
+    aggregate: &Aggregate,
+    passages: usize,
+) -> (Vec<SourceResult>, SearchStats) {
+    let start = Instant::now();
+    let matches: Vec<RankResult> = dt_matrix
+        .axis_iter(Axis(0))
+        .into_par_iter()
+        .enumerate()
+        .map(|(doc_id, doc)| RankResult {
+            doc_id,
+            score: score(query, &doc, op, p),
+        })
+        .filter(|r| r.is_match())
+        .collect();
+    let scoring = start.elapsed();
+
+    let start = Instant::now();
+    let matched = matches.len();
+    let mut grouped = group(matches, sources, aggregate, passages);
+    grouped.truncate(K);
+    let stats = SearchStats {
+        docs_scanned: dt_matrix.nrows(),
+        docs_matched: matched,
+        threads: rayon::current_num_threads(),
+        scoring,
+        merging: start.elapsed(),
+        ..SearchStats::default()
+    };
+    (grouped, stats)
+}
+
+/// every source with matching passages, best first, ties by source
+pub fn group(
+    matches: Vec<RankResult>,
+    sources: &[usize],
+    aggregate: &Aggregate,
+    passages: usize,
+) -> Vec<SourceResult> {
+    let mut by_source: HashMap<usize, Vec<RankResult>> = HashMap::new();
+    for result in matches.into_iter() {
+        by_source
+            .entry(sources[result.doc_id])
+            .or_default()
+            .push(result);
+    }
+    let mut grouped: Vec<SourceResult> = by_source
+        .into_iter()
+        .map(|(source, mut results)| {
+            results.sort();
+            let score = aggregate.score(&results);
+            let matched = results.len();
+            results.truncate(passages);
+            SourceResult {
+                source,
+                score,
+                passages: results,
+                matched,
+            }
+        })
+        .collect();
+    grouped.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.source.cmp(&b.source)));
+    grouped
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::array;
+
+    use crate::ranking::{
+        group::{group, rank_grouped, Aggregate},
+        op::Op,
+        rank::rank,
+        rank_result::RankResult,
+    };
+
+    fn results(scores: &[(usize, f32)]) -> Vec<RankResult> {
+        scores
+            .iter()
+            .map(|(doc_id, score)| RankResult {
+                doc_id: *doc_id,
+                score: *score,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn aggregates_passages() {
+        // source 0 has one great passage, source 1 several good ones
+        let matches = results(&[(0, 0.9), (1, 0.5), (2, 0.4), (3, 0.4), (4, 0.1)]);
+        let sources = [0, 1, 1, 1, 0];
+        let order = |aggregate: Aggregate| -> Vec<(usize, f32)> {
+            group(matches.clone(), &sources, &aggregate, 2)
+                .iter()
+                .map(|g| (g.source, g.score))
+                .collect()
+        };
+        assert_eq!(order(Aggregate::Max), vec![(0, 0.9), (1, 0.5)]);
+        assert_eq!(order(Aggregate::Sum), vec![(1, 1.3), (0, 1.0)]);
+        assert_eq!(order(Aggregate::Mean(2)), vec![(0, 0.5), (1, 0.45)]);
+        assert_eq!(
+            order(Aggregate::Mean(3)),
+            vec![(1, 1.3 / 3.0), (0, 1.0 / 3.0)]
+        );
+
+        let grouped = group(matches, &sources, &Aggregate::Sum, 2);
+        assert_eq!(grouped[0].passages, results(&[(1, 0.5), (2, 0.4)]));
+        assert_eq!(grouped[0].matched, 3);
+    }
+
+    #[test]
+    fn ranks_sources_by_all_passages() {
+        let dtm = array![[0.9, 0.0], [0.0, 0.6], [0.5, 0.5], [0.0, 0.0]];
+        let query = array![1.0, 1.0];
+        let sources = [0, 1, 1, 1];
+        let (grouped, stats) = rank_grouped(
+            &query.view(),
+            &dtm.view(),
+            &sources,
+            &Op::OR,
+            2.0,
+            &Aggregate::Max,
+            3,
+        );
+        let (ranked, _) = rank(&query.view(), &dtm.view(), &Op::OR, 2.0);
+        assert_eq!(grouped[0].score, ranked[0].score);
+        assert_eq!(
+            grouped.iter().map(|g| g.source).collect::<Vec<_>>(),
+            vec![0, 1]
+        );
+        // the empty passage doesn't match
+        assert_eq!(grouped[1].matched, 2);
+        assert_eq!((stats.docs_scanned, stats.docs_matched), (4, 3));
+    }
+
+    #[test]
+    fn parses_aggregates() {
+        assert_eq!("max".parse(), Ok(Aggregate::Max));
+        assert_eq!("sum".parse(), Ok(Aggregate::Sum));
+        assert_eq!("mean".parse(), Ok(Aggregate::Mean(3)));
+        assert_eq!("mean:5".parse(), Ok(Aggregate::Mean(5)));
+        assert!("mean:0".parse::<Aggregate>().is_err());
+        assert!("median".parse::<Aggregate>().is_err());
+        assert!("max:2".parse::<Aggregate>().is_err());
+    }
+}
@@ -0,0 +1,296 @@
+//! Fused p-norm kernels over contiguous weights.
+//!
+//! Each kernel walks query and document weights once, without allocating,
+//! accumulating into `LANES` independent sums. On x86 with AVX2 the sums are
+//! one 256 bit register and every product, power and add is a vector
+//! instruction, otherwise they're a plain array. Both add in the same order
+//! and raise to integer powers by the same squarings, so they return bit for
+//! bit the same scores. Real powers have no vector instruction, their
+//! products are taken 8 at a time and raised one by one.
+use super::op::Op;
+
+#[cfg(target_arch = "x86")]
+use std::arch::x86::*;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+/// independent partial sums, 8 f32 fill a 256 bit register
+const LANES: usize = 8;
+
+/// how a product is raised to p, resolved once per call rather than per weight
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Power {
+    One,
+    Two,
+    Int(u32),
+    Real(f32),
+}
+
+impl Power {
+    fn of(p: f32) -> Power {
+        if p == 1.0 {
+            Power::One
+        } else if p == 2.0 {
+            Power::Two
+        } else if p.fract() == 0.0 && p > 0.0 && p <= 16.0 {
+            Power::Int(p as u32)
+        } else {
+            Power::Real(p)
+        }
+    }
+}
+
+/// ((Σ (a·b)^p) / n)^(1/p), over an n dimensional term space of which a and b cover the first a.len()
+pub fn or_n(a: &[f32], b: &[f32], p: f32, n: usize) -> f32 {
+    let sum = sum(a, b, &Op::OR, p);
+    (sum / n as f32).powf(1.0 / p)
+}
+
+/// 1 - ((Σ (1 - a·b)^p + uncovered) / n)^(1/p), each uncovered term adding (1-0)^p = 1
+pub fn and_n(a: &[f32], b: &[f32], p: f32, n: usize) -> f32 {
+    let sum = sum(a, b, &Op::AND, p);
+    let uncovered = (n - a.len()) as f32;
+    1.0 - ((sum + uncovered) / n as f32).powf(1.0 / p)
+}
+
+/// Σ (a·b)^p for or, Σ (1 - a·b)^p for and, with vector instructions when
+/// the CPU has them
+pub fn sum(a: &[f32], b: &[f32], op: &Op, p: f32) -> f32 {
+    assert_eq!(a.len(), b.len(), "query and document cover different terms");
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if is_x86_feature_detected!("avx2") {
+            // SAFETY: only reached when the CPU supports avx2
+            return unsafe { sum_avx2(a, b, op, Power::of(p)) };
+        }
+    }
+    sum_lanes(a, b, op, Power::of(p))
+}
+
+/// `sum` without vector instructions, for comparing against them
+pub fn sum_portable(a: &[f32], b: &[f32], op: &Op, p: f32) -> f32 {
+    assert_eq!(a.len(), b.len(), "query and document cover different terms");
+    sum_lanes(a, b, op, Power::of(p))
+}
+
+/// each op and power gets its own loop so the hot one has no branches
+fn sum_lanes(a: &[f32], b: &[f32], op: &Op, power: Power) -> f32 {
+    match (op, power) {
+        (Op::OR, Power::One) => lanes(a, b, |x| x),
+        (Op::OR, Power::Two) => lanes(a, b, |x| x * x),
+        (Op::OR, Power::Int(i)) => lanes(a, b, |x| powi(x, i)),
+        (Op::OR, Power::Real(p)) => lanes(a, b, |x| x.powf(p)),
+        (Op::AND, Power::One) => lanes(a, b, |x| 1.0 - x),
+        (Op::AND, Power::Two) => lanes(a, b, |x| (1.0 - x) * (1.0 - x)),
+        (Op::AND, Power::Int(i)) => lanes(a, b, |x| powi(1.0 - x, i)),
+        (Op::AND, Power::Real(p)) => lanes(a, b, |x| (1.0 - x).powf(p)),
+    }
+}
+
+/// Σ f(a·b), lane by lane then across lanes
+#[inline(always)]
+fn lanes<F: Fn(f32) -> f32>(a: &[f32], b: &[f32], f: F) -> f32 {
+    let mut acc = [0.0f32; LANES];
+    let chunks = a.chunks_exact(LANES).zip(b.chunks_exact(LANES));
+    for (ca, cb) in chunks {
+        for ((acc, x), y) in acc.iter_mut().zip(ca).zip(cb) {
+            *acc += f(x * y);
+        }
+    }
+    reduce(acc, a, b, f)
+}
+
+/// add the tail past the last full chunk to the first lane, then the lanes
+/// pairwise in a fixed order
+#[inline(always)]
+fn reduce<F: Fn(f32) -> f32>(mut acc: [f32; LANES], a: &[f32], b: &[f32], f: F) -> f32 {
+    let tail = a.len() - a.len() % LANES;
+    for (x, y) in a[tail..].iter().zip(b[tail..].iter()) {
+        acc[0] += f(x * y);
+    }
+    let mut width = LANES;
+    while width > 1 {
+        width /= 2;
+        for lane in 0..width {
+            acc[lane] += acc[lane + width];
+        }
+    }
+    acc[0]
+}
+
+/// x^n by squaring, the multiplications in the same order as `powi_avx2`'s
+#[inline(always)]
+fn powi(mut x: f32, mut n: u32) -> f32 {
+    let mut r = 1.0;
+    loop {
+        if n & 1 == 1 {
+            r *= x;
+        }
+        n >>= 1;
+        if n == 0 {
+            return r;
+        }
+        x *= x;
+    }
+}
+
+/// Σ vector(a·b) 8 weights at a time, the tail by scalar(a·b), within an
+/// avx2 function so the intrinsics inline
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+macro_rules! lanes_avx2 {
+    ($a:expr, $b:expr, |$v:ident| $vector:expr, |$x:ident| $scalar:expr) => {{
+        let (a, b): (&[f32], &[f32]) = ($a, $b);
+        let mut acc = _mm256_setzero_ps();
+        let chunks = a.len() / LANES;
+        for chunk in 0..chunks {
+            let at = chunk * LANES;
+            let $v = _mm256_mul_ps(
+                _mm256_loadu_ps(a.as_ptr().add(at)),
+                _mm256_loadu_ps(b.as_ptr().add(at)),
+            );
+            acc = _mm256_add_ps(acc, $vector);
+        }
+        let mut lanes = [0.0f32; LANES];
+        _mm256_storeu_ps(lanes.as_mut_ptr(), acc);
+        reduce(lanes, a, b, |$x: f32| $scalar)
+    }};
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn sum_avx2(a: &[f32], b: &[f32], op: &Op, power: Power) -> f32 {
+    let one = _mm256_set1_ps(1.0);
+    match (op, power) {
+        (Op::OR, Power::One) => lanes_avx2!(a, b, |v| v, |x| x),
+        (Op::OR, Power::Two) => lanes_avx2!(a, b, |v| _mm256_mul_ps(v, v), |x| x * x),
+        (Op::OR, Power::Int(i)) => lanes_avx2!(a, b, |v| powi_avx2(v, i), |x| powi(x, i)),
+        (Op::OR, Power::Real(p)) => {
+            lanes_avx2!(a, b, |v| powf_avx2(v, p), |x| x.powf(p))
+        }
+        (Op::AND, Power::One) => lanes_avx2!(a, b, |v| _mm256_sub_ps(one, v), |x| 1.0 - x),
+        (Op::AND, Power::Two) => lanes_avx2!(
+            a,
+            b,
+            |v| {
+                let c = _mm256_sub_ps(one, v);
+                _mm256_mul_ps(c, c)
+            },
+            |x| (1.0 - x) * (1.0 - x)
+        ),
+        (Op::AND, Power::Int(i)) => {
+            lanes_avx2!(a, b, |v| powi_avx2(_mm256_sub_ps(one, v), i), |x| powi(
+                1.0 - x,
+                i
+            ))
+        }
+        (Op::AND, Power::Real(p)) => {
+            lanes_avx2!(a, b, |v| powf_avx2(_mm256_sub_ps(one, v), p), |x| (1.0 - x)
+                .powf(p))
+        }
+    }
+}
+
+/// every lane to the n by squaring, like `powi`
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+#[inline]
+unsafe fn powi_avx2(mut x: __m256, mut n: u32) -> __m256 {
+    let mut r = _mm256_set1_ps(1.0);
+    loop {
+        if n & 1 == 1 {
+            r = _mm256_mul_ps(r, x);
+        }
+        n >>= 1;
+        if n == 0 {
+            return r;
+        }
+        x = _mm256_mul_ps(x, x);
+    }
+}
+
+/// every lane to the p, one at a time as there's no vector powf
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+#[inline]
+unsafe fn powf_avx2(x: __m256, p: f32) -> __m256 {
+    let mut lanes = [0.0f32; LANES];
+    _mm256_storeu_ps(lanes.as_mut_ptr(), x);
+    for lane in lanes.iter_mut() {
+        *lane = lane.powf(p);
+    }
+    _mm256_loadu_ps(lanes.as_ptr())
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{Array1, ArrayView1};
+    use proptest::prelude::*;
+
+    use crate::ranking::{
+        kernel::{and_n, or_n, powi, sum, sum_portable, Power},
+        op::Op,
+    };
+
+    /// what or_n computed before the kernels, allocating a * b
+    fn reference_or_n(a: &ArrayView1<f32>, b: &ArrayView1<f32>, p: f32, n: usize) -> f32 {
+        let c = a * b;
+        let c = c.map(|e| e.powf(p)).sum();
+        (c / n as f32).powf(1.0 / p)
+    }
+
+    /// what and_n computed before the kernels
+    fn reference_and_n(a: &ArrayView1<f32>, b: &ArrayView1<f32>, p: f32, n: usize) -> f32 {
+        let c = a * b;
+        let c = c.map(|e| (1.0 - e).powf(p));
+        let uncovered = (n - a.dim()) as f32;
+        1.0 - ((c.sum() + uncovered) / n as f32).powf(1.0 / p)
+    }
+
+    #[test]
+    fn powers() {
+        assert_eq!(Power::of(1.0), Power::One);
+        assert_eq!(Power::of(2.0), Power::Two);
+        assert_eq!(Power::of(3.0), Power::Int(3));
+        assert_eq!(Power::of(1.5), Power::Real(1.5));
+        assert_eq!(Power::of(100.0), Power::Real(100.0));
+        assert_eq!(powi(0.5, 3), 0.125);
+        assert_eq!(powi(0.9, 1), 0.9);
+    }
+
+    proptest! {
+        #[test]
+        fn matches_reference(
+            (a, b) in (0usize..70).prop_flat_map(|len| (
+                prop::collection::vec(prop_oneof![Just(0.0f32), Just(1.0f32), 0.0f32..1.0], len),
+                prop::collection::vec(prop_oneof![Just(0.0f32), 0.0f32..1.0], len),
+            )),
+            uncovered in 0usize..5,
+            p in prop_oneof![Just(1.0f32), Just(2.0f32), Just(3.0f32), 1.0f32..4.0],
+        ) {
+            let n = a.len() + uncovered;
+            prop_assume!(n > 0);
+            let (va, vb) = (Array1::from(a.clone()), Array1::from(b.clone()));
+            let close = |x: f32, y: f32| (x - y).abs() <= 1e-5 * (1.0 + y.abs());
+            let (or, expected) = (or_n(&a, &b, p, n), reference_or_n(&va.view(), &vb.view(), p, n));
+            prop_assert!(close(or, expected), "or {} != {}", or, expected);
+            let (and, expected) = (and_n(&a, &b, p, n), reference_and_n(&va.view(), &vb.view(), p, n));
+            prop_assert!(close(and, expected), "and {} != {}", and, expected);
+        }
+
+        #[test]
+        fn dispatch_is_exact(
+            (a, b) in (0usize..70).prop_flat_map(|len| (
+                prop::collection::vec(0.0f32..1.0, len),
+                prop::collection::vec(0.0f32..1.0, len),
+            )),
+            p in prop_oneof![Just(1.0f32), Just(2.0f32), Just(3.0f32), 1.0f32..4.0],
+        ) {
+            for op in [Op::AND, Op::OR] {
+                prop_assert_eq!(
+                    sum(&a, &b, &op, p).to_bits(),
+                    sum_portable(&a, &b, &op, p).to_bits()
+                );
+            }
+        }
+    }
+}
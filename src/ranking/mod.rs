@@ -1,7 +1,19 @@
 extern crate blas_src;
 
+pub mod batch;
+pub mod compare;
+pub mod explain;
+pub mod feedback;
+pub mod gather;
+pub mod group;
+pub mod kernel;
+pub mod normalization;
 pub mod op;
+pub mod prefilter;
+pub mod pruned;
 pub mod rank;
 pub mod rank_result;
+pub mod retriever;
 pub mod scorer;
-mod simd_sandbox;
+pub mod stats;
+pub mod top_k;
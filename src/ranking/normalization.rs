@@ -0,0 +1,48 @@
+use ndarray::{Array2, Axis};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Normalization {
+    /// Leave weights as the scorer produced them
+    None,
+    /// Divide every weight by the largest weight in the matrix, so all are in [0, 1]
+    Max,
+    /// Scale each document row to unit length
+    L2,
+}
+
+impl Normalization {
+    /// normalize a document x term matrix in place
+    pub fn apply(&self, dt_matrix: &mut Array2<f32>) {
+        match self {
+            Normalization::None => {}
+            Normalization::Max => {
+                let max = dt_matrix.fold(0.0_f32, |m, w| m.max(*w));
+                if max > 0.0 {
+                    dt_matrix.mapv_inplace(|w| w / max);
+                }
+            }
+            Normalization::L2 => {
+                for mut row in dt_matrix.axis_iter_mut(Axis(0)) {
+                    let norm = row.dot(&row).sqrt();
+                    if norm > 0.0 {
+                        row.mapv_inplace(|w| w / norm);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for Normalization {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Normalization, Self::Err> {
+        match input {
+            "none" => Ok(Normalization::None),
+            "max" => Ok(Normalization::Max),
+            "l2" => Ok(Normalization::L2),
+            _ => Err("unsupported normalization".to_string()),
+        }
+    }
+}
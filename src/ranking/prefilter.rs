@@ -0,0 +1,191 @@
+use super::{
+    op::Op,
+    rank::{finish, rank, score, K},
+    rank_result::RankResult,
+    stats::SearchStats,
+    top_k::TopK,
+};
+use ndarray::{ArrayView1, ArrayView2};
+use std::time::Instant;
+
+/// Which documents a prefiltered AND scores.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Prefilter {
+    /// query terms a document has to hold to be scored, all of them when None
+    pub min_matched: Option<usize>,
+    /// when fewer documents qualify, score every document instead
+    pub min_candidates: usize,
+}
+
+impl Default for Prefilter {
+    fn default() -> Self {
+        Prefilter {
+            min_matched: None,
+            min_candidates: K,
+        }
+    }
+}
+
+/// given an embedded query and a document x term matrix, rank by vboo and
+/// only the documents holding enough of the query terms
+///
+/// Candidates come from intersecting the query terms' postings, the rows of
+/// their columns with weight. Unlike `rank`, a document missing a query term
+/// can't outrank one holding every term however strong its other weights,
+/// unless too few candidates are found and every document is scored after all.
+pub fn rank_prefiltered(
+    query: &ArrayView1<f32>,
+    dt_matrix: &ArrayView2<f32>,
+    p: f32,
+    prefilter: &Prefilter,
+) -> (Vec<RankResult>, SearchStats) {
+    let start = Instant::now();
+    let postings: Vec<Vec<usize>> = query
+        .iter()
+        .enumerate()
+        .filter(|(_, q)| **q != 0.0)
+        .map(|(term, _)| {
+            dt_matrix
+                .column(term)
+                .iter()
+                .enumerate()
+                .filter(|(_, w)| **w != 0.0)
+                .map(|(doc_id, _)| doc_id)
+                .collect()
+        })
+        .collect();
+    let min_matched = prefilter
+        .min_matched
+        .unwrap_or(postings.len())
+        .clamp(1, postings.len().max(1));
+
+    let candidates = intersect(&postings, min_matched);
+    if candidates.len() < prefilter.min_candidates {
+        return rank(query, dt_matrix, &Op::AND, p);
+    }
+
+    let mut topk = TopK::new(K);
+    for doc_id in candidates {
+        let score = score(query, &dt_matrix.row(doc_id), &Op::AND, p);
+        topk.offer(RankResult { doc_id, score });
+    }
+    let scoring = start.elapsed();
+
+    let start = Instant::now();
+    let skipped = dt_matrix.nrows() - topk.offered();
+    let (results, stats) = finish(topk, 1, scoring, start);
+    (
+        results,
+        SearchStats {
+            docs_skipped: skipped,
+            ..stats
+        },
+    )
+}
+
+/// doc ids found in at least min_matched of the ascending postings lists
+fn intersect(postings: &[Vec<usize>], min_matched: usize) -> Vec<usize> {
+    let mut candidates = Vec::new();
+    if postings.len() < min_matched {
+        return candidates;
+    }
+    let mut cursors = vec![0; postings.len()];
+    loop {
+        let next = postings
+            .iter()
+            .zip(cursors.iter())
+            .filter_map(|(docs, cursor)| docs.get(*cursor))
+            .min()
+            .copied();
+        let doc_id = match next {
+            Some(doc_id) => doc_id,
+            None => break,
+        };
+        let mut matched = 0;
+        for (docs, cursor) in postings.iter().zip(cursors.iter_mut()) {
+            if docs.get(*cursor) == Some(&doc_id) {
+                *cursor += 1;
+                matched += 1;
+            }
+        }
+        if matched >= min_matched {
+            candidates.push(doc_id);
+        }
+    }
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{array, Array1, Array2};
+    use ndarray_npy::read_npy;
+
+    use crate::ranking::{
+        op::Op,
+        prefilter::{intersect, rank_prefiltered, Prefilter},
+        rank::{rank, P},
+        rank_result::RankResult,
+    };
+
+    fn ids(results: &[RankResult]) -> Vec<usize> {
+        results.iter().map(|r| r.doc_id).collect()
+    }
+
+    #[test]
+    fn intersects_postings() {
+        let postings = vec![vec![0, 2, 3, 7], vec![2, 3, 5], vec![1, 3, 5, 7]];
+        assert_eq!(intersect(&postings, 3), vec![3]);
+        assert_eq!(intersect(&postings, 2), vec![2, 3, 5, 7]);
+        assert_eq!(intersect(&postings, 1), vec![0, 1, 2, 3, 5, 7]);
+        assert_eq!(intersect(&postings, 4), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn scores_only_docs_with_every_term() {
+        // doc 1 is missing a term but its other weight is strong enough to outrank doc 2
+        let dtm = array![[0., 0., 1.], [0.9, 0., 0.], [0.1, 0.1, 0.], [0.5, 0.5, 0.]];
+        let q = array![1., 1., 0.];
+        let (full, _) = rank(&q.view(), &dtm.view(), &Op::AND, P);
+        assert_eq!(ids(&full), vec![3, 1, 2]);
+
+        let prefilter = Prefilter {
+            min_matched: None,
+            min_candidates: 0,
+        };
+        let (results, stats) = rank_prefiltered(&q.view(), &dtm.view(), P, &prefilter);
+        assert_eq!(ids(&results), vec![3, 2]);
+        assert_eq!(stats.docs_scanned, 2);
+        assert_eq!(stats.docs_skipped, 2);
+        assert_eq!(results[0], full[0]);
+    }
+
+    #[test]
+    fn one_matched_term_is_full_and() {
+        let dtm: Array2<f32> =
+            read_npy("resources/doc_term_matrix.npy").expect("require test file");
+        let q: Array1<f32> = read_npy("resources/query.npy").expect("require test file");
+        let prefilter = Prefilter {
+            min_matched: Some(1),
+            min_candidates: 0,
+        };
+        let (expected, _) = rank(&q.view(), &dtm.view(), &Op::AND, P);
+        let (results, stats) = rank_prefiltered(&q.view(), &dtm.view(), P, &prefilter);
+        assert_eq!(expected, results);
+        assert!(stats.docs_skipped > 0);
+    }
+
+    #[test]
+    fn falls_back_to_full_scoring() {
+        let dtm = array![[0., 0., 1.], [0.9, 0., 0.], [0.1, 0.1, 0.], [0.5, 0.5, 0.]];
+        let q = array![1., 1., 0.];
+        let prefilter = Prefilter {
+            min_matched: None,
+            min_candidates: 3,
+        };
+        let (expected, _) = rank(&q.view(), &dtm.view(), &Op::AND, P);
+        let (results, stats) = rank_prefiltered(&q.view(), &dtm.view(), P, &prefilter);
+        assert_eq!(expected, results);
+        assert_eq!(stats.docs_scanned, 4);
+        assert_eq!(stats.docs_skipped, 0);
+    }
+}
@@ -0,0 +1,187 @@
+use super::{
+    op::Op,
+    rank::{finish, rank, score, K},
+    rank_result::RankResult,
+    stats::SearchStats,
+    top_k::TopK,
+};
+use ndarray::{Array1, ArrayView1, ArrayView2};
+use std::time::Instant;
+
+/// docs with weight for one query term
+struct TermPostings {
+    term: usize,
+    docs: Vec<usize>,
+    max: f32,
+}
+
+/// given an embedded query and a document x term matrix, rank by vboo op
+/// with MaxScore dynamic pruning
+///
+/// Both ops are monotone in every query term's weight, so a document can't
+/// score higher than a document holding the largest weight of each query
+/// term it contains. Query terms are ordered by that largest weight and
+/// once even a document with every weight of the lowest ones couldn't beat
+/// the current top k, documents containing only those terms are skipped.
+/// Documents with none of the query terms score 0 and are always skipped.
+///
+/// Bounds are computed with the same scoring as the documents, so results
+/// are identical to `rank`. Falls back to `rank` when weights could break
+/// monotonicity: any negative, or for AND any query x document weight over 1.
+pub fn rank_pruned(
+    query: &ArrayView1<f32>,
+    dt_matrix: &ArrayView2<f32>,
+    op: &Op,
+    p: f32,
+) -> (Vec<RankResult>, SearchStats) {
+    let start = Instant::now();
+    let mut terms: Vec<TermPostings> = Vec::new();
+    for (term, q) in query.iter().enumerate() {
+        if *q == 0.0 {
+            continue;
+        }
+        let column = dt_matrix.column(term);
+        let docs: Vec<usize> = column
+            .iter()
+            .enumerate()
+            .filter(|(_, w)| **w != 0.0)
+            .map(|(doc_id, _)| doc_id)
+            .collect();
+        let max = column.fold(0.0f32, |max, w| max.max(*w));
+        let min = column.fold(0.0f32, |min, w| min.min(*w));
+        if *q < 0.0 || min < 0.0 || (*op == Op::AND && q * max > 1.0) {
+            return rank(query, dt_matrix, op, p);
+        }
+        terms.push(TermPostings { term, docs, max });
+    }
+    // least promising first, the order terms become non-essential in
+    terms.sort_by(|a, b| (query[a.term] * a.max).total_cmp(&(query[b.term] * b.max)));
+
+    // bounds[i] is the best score of a doc holding only terms[..=i]
+    let mut upper = Array1::zeros(query.dim());
+    let bounds: Vec<f32> = terms
+        .iter()
+        .map(|t| {
+            upper[t.term] = t.max;
+            score(query, &upper.view(), op, p)
+        })
+        .collect();
+
+    let mut topk = TopK::new(K);
+    let mut cursors = vec![0; terms.len()];
+    // terms[essential..] are the ones a doc must hold to be worth scoring
+    let mut essential = 0;
+    loop {
+        while essential < terms.len() && bounds[essential] < topk.min_score() {
+            essential += 1;
+        }
+        let next = (essential..terms.len())
+            .filter_map(|i| terms[i].docs.get(cursors[i]))
+            .min()
+            .copied();
+        let doc_id = match next {
+            Some(doc_id) => doc_id,
+            None => break,
+        };
+        for i in essential..terms.len() {
+            if terms[i].docs.get(cursors[i]) == Some(&doc_id) {
+                cursors[i] += 1;
+            }
+        }
+        let score = score(query, &dt_matrix.row(doc_id), op, p);
+        topk.offer(RankResult { doc_id, score });
+    }
+    let scoring = start.elapsed();
+
+    let start = Instant::now();
+    let skipped = dt_matrix.nrows() - topk.offered();
+    let (results, stats) = finish(topk, 1, scoring, start);
+    (
+        results,
+        SearchStats {
+            docs_skipped: skipped,
+            ..stats
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{array, Array1, Array2};
+    use ndarray_npy::read_npy;
+    use proptest::prelude::*;
+
+    use crate::ranking::{
+        op::Op,
+        pruned::rank_pruned,
+        rank::{rank, K, P},
+    };
+
+    fn weight() -> impl Strategy<Value = f32> {
+        // plenty of exact zeros and repeats so ties and empty docs come up
+        prop_oneof![Just(0.0f32), Just(0.5f32), 0.0f32..1.0]
+    }
+
+    proptest! {
+        #[test]
+        fn same_as_rank(
+            (dtm, query) in (1usize..120, 1usize..8).prop_flat_map(|(docs, terms)| (
+                prop::collection::vec(weight(), docs * terms)
+                    .prop_map(move |w| Array2::from_shape_vec((docs, terms), w).unwrap()),
+                prop::collection::vec(prop_oneof![Just(0.0f32), Just(1.0f32), 0.0f32..1.0], terms)
+                    .prop_map(Array1::from),
+            )),
+            op in prop_oneof![Just(Op::AND), Just(Op::OR)],
+            p in prop_oneof![Just(1.0f32), Just(2.0f32), 1.0f32..4.0],
+        ) {
+            let (expected, _) = rank(&query.view(), &dtm.view(), &op, p);
+            let (pruned, stats) = rank_pruned(&query.view(), &dtm.view(), &op, p);
+            prop_assert_eq!(expected, pruned);
+            prop_assert_eq!(stats.docs_scanned + stats.docs_skipped, dtm.nrows());
+        }
+    }
+
+    #[test]
+    fn same_as_rank_on_fixture() {
+        let dtm: Array2<f32> =
+            read_npy("resources/doc_term_matrix.npy").expect("require test file");
+        let q: Array1<f32> = read_npy("resources/query.npy").expect("require test file");
+        for op in [Op::AND, Op::OR] {
+            let (expected, _) = rank(&q.view(), &dtm.view(), &op, P);
+            let (pruned, stats) = rank_pruned(&q.view(), &dtm.view(), &op, P);
+            assert_eq!(expected, pruned);
+            // docs without any query term are never scored
+            assert!(stats.docs_skipped > 0);
+        }
+    }
+
+    #[test]
+    fn skips_docs_with_only_weak_terms() {
+        // k docs holding the strong term, then docs holding only the weak one
+        let mut dtm = Array2::zeros((K + 20, 2));
+        for doc_id in 0..K {
+            dtm[[doc_id, 1]] = 0.9;
+        }
+        for doc_id in K..K + 20 {
+            dtm[[doc_id, 0]] = 0.1;
+        }
+        let q = array![1., 1.];
+        for op in [Op::AND, Op::OR] {
+            let (expected, _) = rank(&q.view(), &dtm.view(), &op, P);
+            let (pruned, stats) = rank_pruned(&q.view(), &dtm.view(), &op, P);
+            assert_eq!(expected, pruned);
+            assert_eq!(stats.docs_scanned, K);
+            assert_eq!(stats.docs_skipped, 20);
+        }
+    }
+
+    #[test]
+    fn falls_back_when_not_monotone() {
+        let dtm = array![[2., 2.], [0.5, 0.5], [0., 0.]];
+        let q = array![1., 1.];
+        let (expected, _) = rank(&q.view(), &dtm.view(), &Op::AND, 1.5);
+        let (pruned, stats) = rank_pruned(&q.view(), &dtm.view(), &Op::AND, 1.5);
+        assert_eq!(expected, pruned);
+        assert_eq!(stats.docs_skipped, 0);
+    }
+}
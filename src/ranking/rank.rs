@@ -1,21 +1,33 @@
-use super::{op::Op, rank_result::RankResult};
+use super::{kernel, op::Op, rank_result::RankResult, stats::SearchStats, top_k::TopK};
 use ndarray::parallel::prelude::*;
-use ndarray::{ArrayView1, ArrayView2};
+use ndarray::{ArrayView1, ArrayView2, Axis};
 use rayon::prelude::*;
-use std::{collections::BinaryHeap, time::Instant};
+use std::borrow::Cow;
+use std::time::{Duration, Instant};
 
-/// p for p-norm, if set to 1 or == and
-static P: f32 = 2.0;
+/// default p for p-norm, if set to 1 or == and
+pub static P: f32 = 2.0;
+
+/// parse a p for the p-norm, which has to be finite and positive
+pub fn parse_p(input: &str) -> Result<f32, String> {
+    input
+        .trim()
+        .parse()
+        .ok()
+        .filter(|p: &f32| p.is_finite() && *p > 0.0)
+        .ok_or(format!("p must be a finite positive number, got {}", input))
+}
 
 /// k of top k results
-static K: usize = 10;
+pub static K: usize = 10;
 
 /// given an embedded query and a document x term matrix, rank by vboo op
 pub fn rank_parallel_skim(
     query: &ArrayView1<f32>,
     dt_matrix: &ArrayView2<f32>,
     op: &Op,
-) -> Vec<RankResult> {
+    p: f32,
+) -> (Vec<RankResult>, SearchStats) {
     let start = Instant::now();
     // dbg!(query.dim(), dt_matrix.dim());
     let pi = dt_matrix
@@ -25,51 +37,23 @@ pub fn rank_parallel_skim(
         .par_bridge()
         .into_par_iter();
 
-    // can't directly collect into BinaryHeap
-    let results: Vec<BinaryHeap<RankResult>> = pi
+    // each rayon split skims its own top k
+    let results: Vec<TopK> = pi
         .fold(
-            || BinaryHeap::new(),
-            |mut topk: BinaryHeap<RankResult>, (doc_id, doc)| {
-                let score = match op {
-                    Op::AND => and(&query, &doc),
-                    Op::OR => or(&query, &doc),
-                };
-                let rr = RankResult { doc_id, score };
-                if let Some(min) = topk.peek() {
-                    if score.gt(&min.score) {
-                        if topk.len().eq(&K) {
-                            topk.pop();
-                        }
-                        let rr = RankResult { doc_id, score };
-                        topk.push(rr);
-                    }
-                } else {
-                    topk.push(rr);
-                }
+            || TopK::new(K),
+            |mut topk: TopK, (doc_id, doc)| {
+                let score = score(query, &doc, op, p);
+                topk.offer(RankResult { doc_id, score });
                 topk
             },
         )
         .collect();
+    let scoring = start.elapsed();
 
     // we now have to rejoin the split up results
-    let mut returned = BinaryHeap::new();
-    for bh in results.iter() {
-        for e in bh {
-            returned.push(e.clone());
-        }
-    }
-
-    let returned = returned
-        .into_sorted_vec()
-        .iter()
-        .filter(|r| r.score.gt(&0.0))
-        .take(K)
-        .map(|e| *e)
-        .collect();
-    let duration = start.elapsed();
-    println!("Time elapsed in rank_parallel_skim() is: {:?}", duration);
-
-    returned
+    let start = Instant::now();
+    let topk = results.into_iter().fold(TopK::new(K), TopK::merge);
+    finish(topk, rayon::current_num_threads(), scoring, start)
 }
 
 /// given an embedded query and a document x term matrix, rank by vboo op
@@ -77,39 +61,30 @@ pub fn rank_parallel(
     query: &ArrayView1<f32>,
     dt_matrix: &ArrayView2<f32>,
     op: &Op,
-) -> Vec<RankResult> {
+    p: f32,
+) -> (Vec<RankResult>, SearchStats) {
     let start = Instant::now();
     // dbg!(query.dim(), dt_matrix.dim());
-    let pi = dt_matrix
-        .rows()
-        .into_iter()
-        .enumerate()
-        .par_bridge()
-        .into_par_iter();
+    // unlike par_bridge rows are split up front, so results can be reduced pairwise
+    let pi = dt_matrix.axis_iter(Axis(0)).into_par_iter().enumerate();
 
-    let mut results: Vec<RankResult> = pi
-        .map(|(doc_id, doc)| {
-            let score = match op {
-                Op::AND => and(&query, &doc),
-                Op::OR => or(&query, &doc),
-            };
-            let rr = RankResult { doc_id, score };
-            rr
+    let topk = pi
+        .map(|(doc_id, doc)| RankResult {
+            doc_id,
+            score: score(query, &doc, op, p),
         })
-        .collect();
+        .fold(
+            || TopK::new(K),
+            |mut topk, rr| {
+                topk.offer(rr);
+                topk
+            },
+        )
+        .reduce(|| TopK::new(K), TopK::merge);
+    let scoring = start.elapsed();
 
-    // this sorts everything rather than having the topk heap
-    // but there'd be overhead in reusing the same topk heap across threads
-    results.par_sort();
-    let duration = start.elapsed();
-    println!("Time elapsed in rank_parallel() is: {:?}", duration);
-
-    results
-        .iter()
-        .filter(|r| r.score.gt(&0.0))
-        .take(K)
-        .map(|e| *e)
-        .collect()
+    let start = Instant::now();
+    finish(topk, rayon::current_num_threads(), scoring, start)
 }
 
 /// given an embedded query and a document x term matrix, rank by vboo op
@@ -117,64 +92,168 @@ pub fn rank(
     query: &ArrayView1<f32>,
     dt_matrix: &ArrayView2<f32>,
     op: &Op,
-) -> BinaryHeap<RankResult> {
+    p: f32,
+) -> (Vec<RankResult>, SearchStats) {
     let start = Instant::now();
     // dbg!(query.dim(), dt_matrix.dim());
-    // this just preallocates memory for the heap, doesn't enforce max len
-    let mut topk: BinaryHeap<RankResult> = BinaryHeap::with_capacity(K);
+    let mut topk = TopK::new(K);
     for (doc_id, doc) in dt_matrix.rows().into_iter().enumerate() {
-        let score = match op {
-            Op::AND => and(&query, &doc),
-            Op::OR => or(&query, &doc),
-        };
-        if let Some(min) = topk.peek() {
-            if score.gt(&min.score) {
-                if topk.len().eq(&K) {
-                    topk.pop();
-                }
-                let rr = RankResult { doc_id, score };
-                topk.push(rr);
-            }
-        } else {
-            if score.gt(&0.0) {
-                let rr = RankResult { doc_id, score };
-                topk.push(rr);
-            }
-        }
+        let score = score(query, &doc, op, p);
+        topk.offer(RankResult { doc_id, score });
+    }
+    let scoring = start.elapsed();
+
+    let start = Instant::now();
+    finish(topk, 1, scoring, start)
+}
+
+/// sort the collected top k, counting everything since merge_start as merging
+pub(crate) fn finish(
+    topk: TopK,
+    threads: usize,
+    scoring: Duration,
+    merge_start: Instant,
+) -> (Vec<RankResult>, SearchStats) {
+    let stats = SearchStats {
+        docs_scanned: topk.offered(),
+        docs_matched: topk.matched(),
+        threads,
+        scoring,
+        ..SearchStats::default()
+    };
+    let returned = topk.into_sorted_vec();
+    (
+        returned,
+        SearchStats {
+            merging: merge_start.elapsed(),
+            ..stats
+        },
+    )
+}
+
+/// score one document against the query by vboo op
+pub fn score(query: &ArrayView1<f32>, doc: &ArrayView1<f32>, op: &Op, p: f32) -> f32 {
+    score_n(query, doc, op, p, query.dim())
+}
+
+/// score one document against the query by vboo op in an n dimensional term space
+pub fn score_n(query: &ArrayView1<f32>, doc: &ArrayView1<f32>, op: &Op, p: f32, n: usize) -> f32 {
+    match op {
+        Op::AND => and_n(query, doc, p, n),
+        Op::OR => or_n(query, doc, p, n),
     }
-    let duration = start.elapsed();
-    println!("Time elapsed in rank() is: {:?}", duration);
-    topk
 }
 
 /// sqrt((w1^2 + w2^2)/p=2)
 pub fn or(a: &ArrayView1<f32>, b: &ArrayView1<f32>) -> f32 {
-    let c = a * b;
-    let c = c.map(|e| e.powf(P)).sum();
-    (c / a.dim() as f32).powf(1.0 / P)
+    or_p(a, b, P)
 }
 
 /// 1 - sqrt(((1-w1)^2 + (1-w2)^2)/p=2)
 pub fn and(a: &ArrayView1<f32>, b: &ArrayView1<f32>) -> f32 {
-    let c = a * b;
+    and_p(a, b, P)
+}
+
+/// ((w1^p + w2^p)/n)^(1/p)
+pub fn or_p(a: &ArrayView1<f32>, b: &ArrayView1<f32>, p: f32) -> f32 {
+    or_n(a, b, p, a.dim())
+}
+
+/// 1 - (((1-w1)^p + (1-w2)^p)/n)^(1/p)
+pub fn and_p(a: &ArrayView1<f32>, b: &ArrayView1<f32>, p: f32) -> f32 {
+    and_n(a, b, p, a.dim())
+}
+
+/// or_p over an n dimensional term space of which a and b only cover the first
+/// dims, the rest being terms neither has weight for
+pub fn or_n(a: &ArrayView1<f32>, b: &ArrayView1<f32>, p: f32, n: usize) -> f32 {
+    kernel::or_n(&contiguous(a), &contiguous(b), p, n)
+}
+
+/// and_p over an n dimensional term space of which a and b only cover the first
+/// dims, each uncovered term adding (1-0)^p = 1
+pub fn and_n(a: &ArrayView1<f32>, b: &ArrayView1<f32>, p: f32, n: usize) -> f32 {
     // NOTE: some different things I tried (in both)
     // - par_map_inplace way worse performance
     // - fastapprox::pow2 worse performance
     // - reduce_par way worse performance
-    let c = c.map(|e| (1.0 - e).powf(P));
-    1.0 - (c.sum() / a.dim() as f32).powf(1.0 / P)
+    // what stuck is the fused kernel, see kernel.rs
+    kernel::and_n(&contiguous(a), &contiguous(b), p, n)
+}
+
+/// the weights as a slice, copied only when strided (like a column, or a row of a fortran order matrix)
+fn contiguous<'a>(v: &'a ArrayView1<f32>) -> Cow<'a, [f32]> {
+    match v.as_slice() {
+        Some(slice) => Cow::Borrowed(slice),
+        None => Cow::Owned(v.to_vec()),
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use ndarray::{array, Array1, Array2};
+    use ndarray::{array, s, Array1, Array2};
     use ndarray_npy::read_npy;
+    use proptest::prelude::*;
+    use rayon::ThreadPoolBuilder;
 
     use crate::ranking::{
         op::Op,
-        rank::{and, or, rank, rank_parallel, rank_parallel_skim},
+        rank::{
+            and, and_n, and_p, or, or_n, or_p, parse_p, rank, rank_parallel, rank_parallel_skim,
+            score, K, P,
+        },
+        rank_result::RankResult,
     };
 
+    /// score every doc and sort them all, what the top k paths should agree with
+    fn full_sort(query: &Array1<f32>, dtm: &Array2<f32>, op: &Op, p: f32) -> Vec<RankResult> {
+        let mut all: Vec<RankResult> = dtm
+            .rows()
+            .into_iter()
+            .enumerate()
+            .map(|(doc_id, doc)| RankResult {
+                doc_id,
+                score: score(&query.view(), &doc, op, p),
+            })
+            .filter(|r| r.is_match())
+            .collect();
+        all.sort();
+        all.truncate(K);
+        all
+    }
+
+    fn weight() -> impl Strategy<Value = f32> {
+        // plenty of exact zeros and repeats so ties and empty docs come up
+        prop_oneof![Just(0.0f32), Just(0.5f32), 0.0f32..1.0]
+    }
+
+    proptest! {
+        #[test]
+        fn all_rank_agree_with_full_sort(
+            (dtm, query) in (1usize..80, 1usize..8).prop_flat_map(|(docs, terms)| (
+                prop::collection::vec(weight(), docs * terms)
+                    .prop_map(move |w| Array2::from_shape_vec((docs, terms), w).unwrap()),
+                prop::collection::vec(prop_oneof![Just(0.0f32), Just(1.0f32)], terms)
+                    .prop_map(Array1::from),
+            )),
+            op in prop_oneof![Just(Op::AND), Just(Op::OR)],
+            p in prop_oneof![Just(1.0f32), Just(2.0f32), 1.0f32..4.0],
+        ) {
+            let expected = full_sort(&query, &dtm, &op, p);
+            prop_assert_eq!(&expected, &rank(&query.view(), &dtm.view(), &op, p).0);
+            prop_assert_eq!(&expected, &rank_parallel(&query.view(), &dtm.view(), &op, p).0);
+            prop_assert_eq!(&expected, &rank_parallel_skim(&query.view(), &dtm.view(), &op, p).0);
+        }
+    }
+
+    #[test]
+    fn p_is_finite_and_positive() {
+        assert_eq!(parse_p(" 1.5"), Ok(1.5));
+        for bad in ["0", "-1", "NaN", "inf", "-inf", "two"] {
+            assert!(parse_p(bad).is_err(), "{}", bad);
+        }
+    }
+
     #[test]
     fn all_rank_same_top_one() {
         let dtm: Array2<f32> =
@@ -182,11 +261,98 @@ mod tests {
         assert_eq!(dtm.dim(), (126, 293));
         let q: Array1<f32> = read_npy("resources/query.npy").expect("require test file");
         assert_eq!(q.dim(), 293);
-        let rp = rank_parallel(&q.view(), &dtm.view(), &Op::AND);
-        let rps = rank_parallel_skim(&q.view(), &dtm.view(), &Op::AND);
-        let r = rank(&q.view(), &dtm.view(), &Op::AND).into_sorted_vec();
-        assert_eq!(rp.get(0), rps.get(0));
-        assert_eq!(rp.get(0), r.get(0));
+        let rp = rank_parallel(&q.view(), &dtm.view(), &Op::AND, P).0;
+        let rps = rank_parallel_skim(&q.view(), &dtm.view(), &Op::AND, P).0;
+        let r = rank(&q.view(), &dtm.view(), &Op::AND, P).0;
+        assert_eq!(rp.first(), rps.first());
+        assert_eq!(rp.first(), r.first());
+    }
+
+    #[test]
+    fn all_rank_same_stats() {
+        let dtm: Array2<f32> =
+            read_npy("resources/doc_term_matrix.npy").expect("require test file");
+        let q: Array1<f32> = read_npy("resources/query.npy").expect("require test file");
+        let pool = ThreadPoolBuilder::new().num_threads(3).build().unwrap();
+        let (_, rp) = pool.install(|| rank_parallel(&q.view(), &dtm.view(), &Op::OR, P));
+        let (_, rps) = pool.install(|| rank_parallel_skim(&q.view(), &dtm.view(), &Op::OR, P));
+        let (_, r) = rank(&q.view(), &dtm.view(), &Op::OR, P);
+        assert_eq!(r.docs_scanned, 126);
+        assert!(r.docs_matched > 0 && r.docs_matched < 126);
+        assert_eq!(r.threads, 1);
+        for stats in [rp, rps] {
+            assert_eq!(stats.docs_scanned, r.docs_scanned);
+            assert_eq!(stats.docs_matched, r.docs_matched);
+            assert_eq!(stats.threads, 3);
+        }
+    }
+
+    #[test]
+    fn all_rank_same_across_threads() {
+        let dtm: Array2<f32> =
+            read_npy("resources/doc_term_matrix.npy").expect("require test file");
+        let q: Array1<f32> = read_npy("resources/query.npy").expect("require test file");
+        for op in [Op::AND, Op::OR] {
+            let expected = rank(&q.view(), &dtm.view(), &op, P).0;
+            for threads in [1, 2, 3, 8] {
+                let pool = ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build()
+                    .unwrap();
+                let (rp, rps) = pool.install(|| {
+                    (
+                        rank_parallel(&q.view(), &dtm.view(), &op, P).0,
+                        rank_parallel_skim(&q.view(), &dtm.view(), &op, P).0,
+                    )
+                });
+                assert_eq!(expected, rp, "rank_parallel {:?} {} threads", op, threads);
+                assert_eq!(
+                    expected, rps,
+                    "rank_parallel_skim {:?} {} threads",
+                    op, threads
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn ties_ranked_by_doc_id() {
+        // 12 identical docs, more than k, so only the lowest doc ids are kept
+        let dtm = Array2::from_elem((12, 2), 0.5);
+        let q = array![1., 1.];
+        let expected: Vec<usize> = (0..10).collect();
+        let ids = |r: Vec<RankResult>| r.iter().map(|r| r.doc_id).collect::<Vec<usize>>();
+        let pool = ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+        pool.install(|| {
+            assert_eq!(
+                expected,
+                ids(rank_parallel(&q.view(), &dtm.view(), &Op::OR, P).0)
+            );
+            assert_eq!(
+                expected,
+                ids(rank_parallel_skim(&q.view(), &dtm.view(), &Op::OR, P).0)
+            );
+        });
+        assert_eq!(expected, ids(rank(&q.view(), &dtm.view(), &Op::OR, P).0));
+    }
+
+    #[test]
+    fn nan_scores_rejected() {
+        // weights over 1 give a negative base, which powf can't take to p=1.5
+        let dtm = array![[2., 2.], [0.5, 0.5]];
+        let q = array![1., 1.];
+        assert!(and_p(&q.view(), &dtm.row(0), 1.5).is_nan());
+        let expected = vec![1];
+        let ids = |r: Vec<RankResult>| r.iter().map(|r| r.doc_id).collect::<Vec<usize>>();
+        assert_eq!(
+            expected,
+            ids(rank_parallel(&q.view(), &dtm.view(), &Op::AND, 1.5).0)
+        );
+        assert_eq!(
+            expected,
+            ids(rank_parallel_skim(&q.view(), &dtm.view(), &Op::AND, 1.5).0)
+        );
+        assert_eq!(expected, ids(rank(&q.view(), &dtm.view(), &Op::AND, 1.5).0));
     }
 
     #[test]
@@ -241,6 +407,23 @@ mod tests {
         assert_eq!(1., and(&a.view(), &b.view()));
     }
 
+    #[test]
+    fn p_one_is_mean() {
+        let a = array![1., 0.];
+        let b = array![0.5, 1.];
+        assert_eq!(0.25, or_p(&a.view(), &b.view(), 1.0));
+        assert_eq!(0.25, and_p(&a.view(), &b.view(), 1.0));
+    }
+
+    #[test]
+    fn uncovered_terms_are_zero_weight() {
+        let a = array![1., 0., 1., 0.];
+        let b = array![0.5, 1., 0., 0.];
+        let (a2, b2) = (a.slice(s![..2]), b.slice(s![..2]));
+        assert_eq!(or_p(&a.view(), &b.view(), 2.0), or_n(&a2, &b2, 2.0, 4));
+        assert_eq!(and_p(&a.view(), &b.view(), 2.0), and_n(&a2, &b2, 2.0, 4));
+    }
+
     #[test]
     fn sanity_check() {
         let a = array![1., 0.];
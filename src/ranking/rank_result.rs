@@ -1,14 +1,24 @@
 use std::cmp::Ordering;
 
+/// Ordered best first: higher scores sort before lower ones, equal scores by
+/// ascending `doc_id`, and NaN scores after everything else. The order is
+/// total, so sorting or heaping results is deterministic however they arrive.
 #[derive(Debug, Copy, Clone)]
 pub struct RankResult {
     pub doc_id: usize,
     pub score: f32,
 }
 
+impl RankResult {
+    /// whether this result is worth returning at all, NaN and 0 scores are not
+    pub fn is_match(&self) -> bool {
+        self.score > 0.0
+    }
+}
+
 impl PartialEq for RankResult {
     fn eq(&self, other: &Self) -> bool {
-        self.score.eq(&other.score)
+        self.cmp(other) == Ordering::Equal
     }
 }
 
@@ -16,13 +26,61 @@ impl Eq for RankResult {}
 
 impl PartialOrd for RankResult {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        // reverse order for min heap behavior during ranking
-        other.score.partial_cmp(&self.score)
+        Some(self.cmp(other))
     }
 }
 
 impl Ord for RankResult {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.partial_cmp(other).unwrap()
+        // reverse order for min heap behavior during ranking
+        let by_score = match (self.score.is_nan(), other.score.is_nan()) {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Greater,
+            (false, true) => Ordering::Less,
+            (false, false) => other.score.partial_cmp(&self.score).unwrap(),
+        };
+        by_score.then_with(|| self.doc_id.cmp(&other.doc_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BinaryHeap;
+
+    use crate::ranking::rank_result::RankResult;
+
+    fn rr(doc_id: usize, score: f32) -> RankResult {
+        RankResult { doc_id, score }
+    }
+
+    #[test]
+    fn ties_break_on_doc_id() {
+        let mut results = [rr(3, 0.5), rr(1, 0.5), rr(2, 0.9), rr(0, 0.1)];
+        results.sort();
+        let ids: Vec<usize> = results.iter().map(|r| r.doc_id).collect();
+        assert_eq!(ids, vec![2, 1, 3, 0]);
+        assert_ne!(rr(1, 0.5), rr(3, 0.5));
+        assert_eq!(rr(1, 0.5), rr(1, 0.5));
+    }
+
+    #[test]
+    fn nan_sorts_last() {
+        let mut results = [rr(0, f32::NAN), rr(1, 0.0), rr(2, f32::NAN), rr(3, 0.7)];
+        results.sort();
+        let ids: Vec<usize> = results.iter().map(|r| r.doc_id).collect();
+        assert_eq!(ids, vec![3, 1, 0, 2]);
+        assert!(!rr(0, f32::NAN).is_match());
+        assert!(!rr(0, 0.0).is_match());
+    }
+
+    #[test]
+    fn heap_peeks_worst() {
+        let heap: BinaryHeap<RankResult> =
+            vec![rr(0, 0.3), rr(1, f32::NAN), rr(2, 0.3), rr(3, 0.8)]
+                .into_iter()
+                .collect();
+        assert_eq!(heap.peek().unwrap().doc_id, 1);
+        let ids: Vec<usize> = heap.into_sorted_vec().iter().map(|r| r.doc_id).collect();
+        assert_eq!(ids, vec![3, 0, 2, 1]);
     }
 }
@@ -0,0 +1,332 @@
+use super::{
+    gather::rank_gathered,
+    normalization::Normalization,
+    op::Op,
+    pruned::rank_pruned,
+    rank::{finish, rank, rank_parallel, rank_parallel_skim, K},
+    rank_result::RankResult,
+    scorer::Scorer,
+    stats::SearchStats,
+    top_k::TopK,
+};
+use ndarray::parallel::prelude::*;
+use ndarray::{ArrayView1, ArrayView2, Axis};
+use std::{fmt, str::FromStr, time::Instant};
+
+/// Anything ranking the documents of a document x term matrix for an
+/// embedded query, so retrieval models can be swapped for one another.
+pub trait Retriever {
+    /// the top k documents, best first
+    fn retrieve(
+        &self,
+        query: &ArrayView1<f32>,
+        dt_matrix: &ArrayView2<f32>,
+    ) -> (Vec<RankResult>, SearchStats);
+}
+
+/// signature every `rank*` function shares
+pub type RankFn =
+    fn(&ArrayView1<f32>, &ArrayView2<f32>, &Op, f32) -> (Vec<RankResult>, SearchStats);
+
+/// The extended boolean model, ranked by one of the `rank*` functions.
+#[derive(Clone, Copy)]
+pub struct PNorm {
+    pub op: Op,
+    pub p: f32,
+    pub rank: RankFn,
+}
+
+impl Retriever for PNorm {
+    fn retrieve(
+        &self,
+        query: &ArrayView1<f32>,
+        dt_matrix: &ArrayView2<f32>,
+    ) -> (Vec<RankResult>, SearchStats) {
+        (self.rank)(query, dt_matrix, &self.op, self.p)
+    }
+}
+
+/// Strict boolean retrieval, a document matching when it holds all of the
+/// query's terms for and or any of them for or. Every match scores 1, so
+/// they come in doc id order, and a query without terms matches nothing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Boolean {
+    pub op: Op,
+}
+
+impl Retriever for Boolean {
+    fn retrieve(
+        &self,
+        query: &ArrayView1<f32>,
+        dt_matrix: &ArrayView2<f32>,
+    ) -> (Vec<RankResult>, SearchStats) {
+        let has_terms = query.iter().any(|q| *q > 0.0);
+        top_k(dt_matrix, |doc| {
+            let mut terms = query
+                .iter()
+                .zip(doc.iter())
+                .filter(|(q, _)| **q > 0.0)
+                .map(|(_, d)| *d > 0.0);
+            let matches = match self.op {
+                Op::AND => terms.all(|held| held),
+                Op::OR => terms.any(|held| held),
+            };
+            if has_terms && matches {
+                1.0
+            } else {
+                0.0
+            }
+        })
+    }
+}
+
+/// The vector space model, scoring by the cosine of the angle between the
+/// query and the document.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cosine;
+
+impl Retriever for Cosine {
+    fn retrieve(
+        &self,
+        query: &ArrayView1<f32>,
+        dt_matrix: &ArrayView2<f32>,
+    ) -> (Vec<RankResult>, SearchStats) {
+        let query_norm = query.dot(query).sqrt();
+        top_k(dt_matrix, |doc| {
+            let norm = query_norm * doc.dot(&doc).sqrt();
+            if norm > 0.0 {
+                query.dot(&doc) / norm
+            } else {
+                0.0
+            }
+        })
+    }
+}
+
+/// The document's weights summed over the query's terms, which is BM25 when
+/// the index is weighted by the bm25 scorer without normalization, and
+/// `Model::check` rejects it otherwise.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bm25Sum;
+
+impl Retriever for Bm25Sum {
+    fn retrieve(
+        &self,
+        query: &ArrayView1<f32>,
+        dt_matrix: &ArrayView2<f32>,
+    ) -> (Vec<RankResult>, SearchStats) {
+        top_k(dt_matrix, |doc| query.dot(&doc))
+    }
+}
+
+/// score every document in parallel, keeping the top k like `rank_parallel`
+fn top_k<F>(dt_matrix: &ArrayView2<f32>, score: F) -> (Vec<RankResult>, SearchStats)
+where
+    F: Fn(ArrayView1<f32>) -> f32 + Sync,
+{
+    let start = Instant::now();
+    let topk = dt_matrix
+        .axis_iter(Axis(0))
+        .into_par_iter()
+        .enumerate()
+        .map(|(doc_id, doc)| RankResult {
+            doc_id,
+            score: score(doc),
+        })
+        .fold(
+            || TopK::new(K),
+            |mut topk, rr| {
+                topk.offer(rr);
+                topk
+            },
+        )
+        .reduce(|| TopK::new(K), TopK::merge);
+    let scoring = start.elapsed();
+
+    let start = Instant::now();
+    finish(topk, rayon::current_num_threads(), scoring, start)
+}
+
+/// Retrieval models by name, the p-norm ones by how they're ranked.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Model {
+    /// `rank_parallel`
+    PNorm,
+    /// `rank_parallel_skim`
+    Skim,
+    /// `rank`
+    Sequential,
+    /// `rank_pruned`
+    Pruned,
+    /// `rank_gathered`
+    Gathered,
+    Boolean,
+    Cosine,
+    BM25,
+}
+
+/// every model by its name
+static MODELS: [(&str, Model); 8] = [
+    ("pnorm", Model::PNorm),
+    ("pnorm-skim", Model::Skim),
+    ("pnorm-sequential", Model::Sequential),
+    ("pnorm-pruned", Model::Pruned),
+    ("pnorm-gathered", Model::Gathered),
+    ("boolean", Model::Boolean),
+    ("cosine", Model::Cosine),
+    ("bm25", Model::BM25),
+];
+
+impl Model {
+    pub fn names() -> Vec<&'static str> {
+        MODELS.iter().map(|(name, _)| *name).collect()
+    }
+
+    /// the model ranking by op and p, which only the p-norm and boolean ones use
+    pub fn retriever(&self, op: Op, p: f32) -> Box<dyn Retriever> {
+        let pnorm = |rank: RankFn| -> Box<dyn Retriever> { Box::new(PNorm { op, p, rank }) };
+        match self {
+            Model::PNorm => pnorm(rank_parallel),
+            Model::Skim => pnorm(rank_parallel_skim),
+            Model::Sequential => pnorm(rank),
+            Model::Pruned => pnorm(rank_pruned),
+            Model::Gathered => pnorm(rank_gathered),
+            Model::Boolean => Box::new(Boolean { op }),
+            Model::Cosine => Box::new(Cosine),
+            Model::BM25 => Box::new(Bm25Sum),
+        }
+    }
+
+    /// whether the model means what it's named over an index weighted this way
+    pub fn check(&self, scorer: Scorer, normalization: Normalization) -> Result<(), String> {
+        match self {
+            Model::BM25 if scorer != Scorer::BM25 || normalization != Normalization::None => {
+                Err(format!(
+                    "bm25 needs scorer bm25 and norm none, not scorer {:?} and norm {:?}",
+                    scorer, normalization
+                )
+                .to_lowercase())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl FromStr for Model {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Model, Self::Err> {
+        MODELS
+            .iter()
+            .find(|(name, _)| *name == input)
+            .map(|(_, model)| *model)
+            .ok_or(format!(
+                "unsupported model {}, expected one of {}",
+                input,
+                Model::names().join(", ")
+            ))
+    }
+}
+
+impl fmt::Display for Model {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (name, _) = MODELS
+            .iter()
+            .find(|(_, model)| model == self)
+            .expect("every model is named");
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{array, Array1, Array2};
+    use ndarray_npy::read_npy;
+
+    use crate::ranking::{
+        normalization::Normalization,
+        op::Op,
+        rank::rank_parallel,
+        retriever::{Bm25Sum, Boolean, Cosine, Model, Retriever},
+        scorer::Scorer,
+    };
+
+    fn ids(retriever: &dyn Retriever, query: &Array1<f32>, dtm: &Array2<f32>) -> Vec<usize> {
+        retriever
+            .retrieve(&query.view(), &dtm.view())
+            .0
+            .iter()
+            .map(|r| r.doc_id)
+            .collect()
+    }
+
+    #[test]
+    fn pnorm_models_agree() {
+        let dtm: Array2<f32> =
+            read_npy("resources/doc_term_matrix.npy").expect("require test file");
+        let q: Array1<f32> = read_npy("resources/query.npy").expect("require test file");
+        for op in [Op::AND, Op::OR] {
+            let (expected, _) = rank_parallel(&q.view(), &dtm.view(), &op, 2.0);
+            let expected: Vec<usize> = expected.iter().map(|r| r.doc_id).collect();
+            for name in Model::names().iter().filter(|n| n.starts_with("pnorm")) {
+                let model: Model = name.parse().unwrap();
+                assert_eq!(model.to_string(), *name);
+                assert_eq!(
+                    ids(&*model.retriever(op, 2.0), &q, &dtm),
+                    expected,
+                    "{}",
+                    name
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn baselines_rank_differently() {
+        // terms: rust, company, support
+        let dtm = array![
+            [0.2, 0.2, 0.2],
+            [0.9, 0.0, 0.0],
+            [0.0, 0.3, 0.0],
+            [0.0, 0.0, 0.0],
+            [0.4, 0.4, 0.0],
+        ];
+        let query = array![1.0, 1.0, 0.0];
+        // only docs holding both terms, in doc id order
+        assert_eq!(ids(&Boolean { op: Op::AND }, &query, &dtm), vec![0, 4]);
+        assert_eq!(ids(&Boolean { op: Op::OR }, &query, &dtm), vec![0, 1, 2, 4]);
+        // the doc only about the query's terms first, then by the angle
+        assert_eq!(ids(&Cosine, &query, &dtm), vec![4, 0, 1, 2]);
+        // the heaviest weights first, however they're spread
+        assert_eq!(ids(&Bm25Sum, &query, &dtm), vec![1, 4, 0, 2]);
+    }
+
+    #[test]
+    fn parses_models() {
+        for name in Model::names() {
+            assert_eq!(name.parse::<Model>().unwrap().to_string(), name);
+        }
+        assert!("lsi".parse::<Model>().is_err());
+    }
+
+    #[test]
+    fn boolean_without_terms_matches_nothing() {
+        let dtm = array![[0.2, 0.2], [0.9, 0.0]];
+        let query = array![0.0, 0.0];
+        for op in [Op::AND, Op::OR] {
+            assert!(ids(&Boolean { op }, &query, &dtm).is_empty());
+        }
+    }
+
+    #[test]
+    fn bm25_needs_its_weights() {
+        assert!(Model::BM25.check(Scorer::BM25, Normalization::None).is_ok());
+        assert!(Model::BM25
+            .check(Scorer::TFIDF, Normalization::None)
+            .is_err());
+        assert!(Model::BM25.check(Scorer::BM25, Normalization::Max).is_err());
+        assert!(Model::Cosine
+            .check(Scorer::TFIDF, Normalization::Max)
+            .is_ok());
+    }
+}
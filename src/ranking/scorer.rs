@@ -1,6 +1,6 @@
 use std::str::FromStr;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Scorer {
     /// More sensitive to document length because no normalization
     TFIDF,
@@ -8,6 +8,37 @@ pub enum Scorer {
     BM25,
 }
 
+/// bm25 term frequency saturation
+static K1: f32 = 1.2;
+/// bm25 length normalization
+static B: f32 = 0.75;
+
+impl Scorer {
+    /// weight of a term in a document
+    ///
+    /// `doc_len` counts distinct terms, `doc_freq` is how many of `docs` contain the term
+    pub fn weight(
+        &self,
+        freq: usize,
+        doc_len: usize,
+        doc_freq: usize,
+        docs: usize,
+        avg_doc_len: f32,
+    ) -> f32 {
+        let tf = freq as f32 / doc_len as f32;
+        let idf = (docs as f32 / doc_freq as f32).ln();
+        match self {
+            Scorer::TFIDF => tf * idf,
+            Scorer::BM25 => {
+                let bm25 = idf
+                    * (tf * (K1 + 1.0) / tf + K1 * (1.0 - B + B * doc_len as f32 / avg_doc_len));
+                // we hack in a scaling factor so we're beneath 1, otherwise AND breaks
+                bm25 * 0.01
+            }
+        }
+    }
+}
+
 impl FromStr for Scorer {
     type Err = String;
 
@@ -0,0 +1,42 @@
+use std::{fmt, time::Duration};
+
+/// What a ranking call did, returned alongside its results so library code
+/// never has to print.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchStats {
+    /// documents scored against the query
+    pub docs_scanned: usize,
+    /// documents pruning ruled out without scoring
+    pub docs_skipped: usize,
+    /// documents scoring above the collector's threshold
+    pub docs_matched: usize,
+    /// rayon threads available to the search, 1 when sequential
+    pub threads: usize,
+    /// scanning and scoring into per thread top k, including any merging done
+    /// while reducing in parallel
+    pub scoring: Duration,
+    /// joining per thread top k and sorting the final results
+    pub merging: Duration,
+}
+
+impl SearchStats {
+    pub fn elapsed(&self) -> Duration {
+        self.scoring + self.merging
+    }
+}
+
+impl fmt::Display for SearchStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "scanned {} docs, skipped {}, matched {}, {} threads, scoring {:?}, merging {:?}, total {:?}",
+            self.docs_scanned,
+            self.docs_skipped,
+            self.docs_matched,
+            self.threads,
+            self.scoring,
+            self.merging,
+            self.elapsed()
+        )
+    }
+}
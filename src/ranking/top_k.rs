@@ -0,0 +1,237 @@
+use super::rank_result::RankResult;
+use std::collections::BinaryHeap;
+
+/// Bounded collector of the best k results.
+///
+/// Internally a min heap (by `RankResult` ordering the worst kept result is
+/// on top), so offering a result is O(log k). Collectors built on different
+/// threads can be merged, which is what the rayon folds in `rank` do.
+#[derive(Debug, Clone)]
+pub struct TopK {
+    k: usize,
+    threshold: f32,
+    heap: BinaryHeap<RankResult>,
+    offered: usize,
+    matched: usize,
+}
+
+impl TopK {
+    /// keeps results scoring above 0
+    pub fn new(k: usize) -> TopK {
+        TopK::with_threshold(k, 0.0)
+    }
+
+    /// keeps results scoring strictly above threshold
+    pub fn with_threshold(k: usize, threshold: f32) -> TopK {
+        TopK {
+            k,
+            threshold,
+            // this just preallocates memory for the heap, doesn't enforce max len
+            heap: BinaryHeap::with_capacity(k),
+            offered: 0,
+            matched: 0,
+        }
+    }
+
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    pub fn threshold(&self) -> f32 {
+        self.threshold
+    }
+
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.heap.len() >= self.k
+    }
+
+    /// how many results have been offered, including to merged collectors
+    pub fn offered(&self) -> usize {
+        self.offered
+    }
+
+    /// how many offered results cleared the threshold, kept or not
+    pub fn matched(&self) -> usize {
+        self.matched
+    }
+
+    /// the worst kept result, the one the next offer has to beat once full
+    pub fn worst(&self) -> Option<&RankResult> {
+        self.heap.peek()
+    }
+
+    /// the score a new result has to beat to be kept
+    pub fn min_score(&self) -> f32 {
+        match self.heap.peek() {
+            Some(worst) if self.is_full() => worst.score,
+            _ => self.threshold,
+        }
+    }
+
+    /// keep rr if it clears the threshold and beats the worst of the k kept so far,
+    /// returns whether it was kept
+    ///
+    /// comparing whole results rather than scores breaks ties on doc id, so which
+    /// docs are kept doesn't depend on the order they're offered in
+    pub fn offer(&mut self, rr: RankResult) -> bool {
+        self.offered += 1;
+        if rr.score.is_nan() || rr.score <= self.threshold {
+            return false;
+        }
+        self.matched += 1;
+        self.keep(rr)
+    }
+
+    fn keep(&mut self, rr: RankResult) -> bool {
+        if self.k == 0 {
+            return false;
+        }
+        if self.heap.len() < self.k {
+            self.heap.push(rr);
+            return true;
+        }
+        match self.heap.peek_mut() {
+            Some(mut worst) if rr < *worst => {
+                *worst = rr;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// combine with a collector over other documents, as if all were offered to one
+    ///
+    /// both should have the same k and threshold, the merged one keeps the larger's
+    pub fn merge(self, other: TopK) -> TopK {
+        let (mut into, from) = if self.heap.len() >= other.heap.len() {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        into.offered += from.offered;
+        into.matched += from.matched;
+        for rr in from.heap.into_iter() {
+            into.keep(rr);
+        }
+        into
+    }
+
+    /// best first
+    pub fn into_sorted_vec(self) -> Vec<RankResult> {
+        self.heap.into_sorted_vec()
+    }
+}
+
+impl Extend<RankResult> for TopK {
+    fn extend<I: IntoIterator<Item = RankResult>>(&mut self, iter: I) {
+        for rr in iter {
+            self.offer(rr);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use crate::ranking::{rank_result::RankResult, top_k::TopK};
+
+    fn results(scores: &[f32]) -> Vec<RankResult> {
+        scores
+            .iter()
+            .enumerate()
+            .map(|(doc_id, score)| RankResult {
+                doc_id,
+                score: *score,
+            })
+            .collect()
+    }
+
+    fn full_sort(rs: &[RankResult], k: usize, threshold: f32) -> Vec<RankResult> {
+        let mut expected: Vec<RankResult> =
+            rs.iter().filter(|r| r.score > threshold).copied().collect();
+        expected.sort();
+        expected.truncate(k);
+        expected
+    }
+
+    #[test]
+    fn fills_before_filtering() {
+        // the worst kept result must not stop better ones while there's still room
+        let mut topk = TopK::new(3);
+        topk.extend(results(&[0.9, 0.1, 0.5, 0.3]));
+        let ids: Vec<usize> = topk.into_sorted_vec().iter().map(|r| r.doc_id).collect();
+        assert_eq!(ids, vec![0, 2, 3]);
+    }
+
+    #[test]
+    fn threshold_and_nan() {
+        let mut topk = TopK::with_threshold(10, 0.2);
+        assert!(!topk.offer(RankResult {
+            doc_id: 0,
+            score: f32::NAN
+        }));
+        assert!(!topk.offer(RankResult {
+            doc_id: 1,
+            score: 0.2
+        }));
+        assert!(topk.offer(RankResult {
+            doc_id: 2,
+            score: 0.25
+        }));
+        assert_eq!(topk.len(), 1);
+        assert_eq!(topk.min_score(), 0.2);
+        assert_eq!(topk.offered(), 3);
+        assert_eq!(topk.matched(), 1);
+    }
+
+    #[test]
+    fn zero_k_keeps_nothing() {
+        let mut topk = TopK::new(0);
+        assert!(!topk.offer(RankResult {
+            doc_id: 0,
+            score: 1.0
+        }));
+        assert!(topk.is_empty());
+    }
+
+    proptest! {
+        #[test]
+        fn matches_full_sort(
+            scores in prop::collection::vec(prop_oneof![Just(0.0f32), Just(0.5f32), 0.0f32..1.0], 0..200),
+            k in 0usize..20,
+            threshold in prop_oneof![Just(0.0f32), 0.0f32..0.5],
+        ) {
+            let rs = results(&scores);
+            let mut topk = TopK::with_threshold(k, threshold);
+            topk.extend(rs.iter().copied());
+            prop_assert_eq!(topk.into_sorted_vec(), full_sort(&rs, k, threshold));
+        }
+
+        #[test]
+        fn merge_matches_single(
+            scores in prop::collection::vec(prop_oneof![Just(0.5f32), 0.0f32..1.0], 0..200),
+            k in 1usize..20,
+            split in 0usize..200,
+        ) {
+            let rs = results(&scores);
+            let split = split.min(rs.len());
+            let mut left = TopK::new(k);
+            left.extend(rs[..split].iter().copied());
+            let mut right = TopK::new(k);
+            right.extend(rs[split..].iter().copied());
+            let merged = left.merge(right);
+            prop_assert_eq!(merged.offered(), rs.len());
+            prop_assert_eq!(merged.matched(), rs.iter().filter(|r| r.score > 0.0).count());
+            prop_assert_eq!(merged.into_sorted_vec(), full_sort(&rs, k, 0.0));
+        }
+    }
+}